@@ -1,71 +1,144 @@
 use crate::cell::Cell;
+use crate::dump::{self, DumpFormat};
 use std::fmt::{Formatter, Error, Debug};
-use std::cmp::min;
+use std::mem;
 
-pub struct VecBuffer<T: Cell> {
-    buffer: Vec<T>,
-}
+mod paged_buffer;
+pub use paged_buffer::PagedBuffer;
+mod deque_buffer;
+pub use deque_buffer::DequeBuffer;
 
-impl<T> VecBuffer<T>
-    where
-        T: Cell
-{
-    pub fn set_value(&mut self, index: usize, value: T) {
-        self.buffer[index] = value;
+/// The memory of the interpreter. Cells that were never written are `0`.
+pub trait Tape<T: Cell> {
+    fn get_value(&self, index: usize) -> T;
+    fn set_value(&mut self, index: usize, value: T);
+
+    fn size(&self) -> usize;
+
+    /// The number of bytes that are allocated for cells.
+    fn memory_usage(&self) -> usize;
+
+    /// The allocated parts of the tape as `(start, cells)` in ascending order.
+    /// All cells outside of the segments are `0`.
+    fn segments(&self) -> Vec<(usize, Vec<T>)>;
+
+    /// Sets all cells to `0`.
+    fn clear(&mut self);
+
+    /// Moves the index `by` cells to the right and returns the new index.
+    /// The index wraps around at the end.
+    fn increase_index(&mut self, index: usize, by: usize) -> usize {
+        let size = self.size();
+        (index + by % size) % size
     }
-    pub fn get_value(&self, index: usize) -> T {
-        self.buffer[index]
+
+    /// Moves the index `by` cells to the left and returns the new index.
+    /// The index wraps around at the start.
+    fn decrease_index(&mut self, index: usize, by: usize) -> usize {
+        let size = self.size();
+        (index + size - by % size) % size
     }
 
-    pub fn size(&self) -> usize {
-        self.buffer.len()
+    /// The index of the cell where the program started. Tapes that grow to
+    /// the left have cells with negative positions in front of it.
+    fn origin(&self) -> usize {
+        0
     }
 
-    pub fn write_buffer(&self, f: &mut Formatter<'_>, start: usize, end: usize) -> Result<(), Error> {
-        const ROW_SIZE: usize = 32;
-        const SEPARATOR: usize = 8;
+    /// Returns `true` if the tape can have this size and origin. The size can
+    /// come from an untrusted snapshot, nothing is changed or allocated.
+    fn can_load(&self, size: usize, origin: usize, _segments: &[(usize, Vec<T>)]) -> bool {
+        size == self.size() && origin == 0
+    }
 
-        write!(f, "Buffer {{\n").unwrap();
+    /// Replaces the content with the given segments, as returned by `segments`.
+    /// Returns `false` if `can_load` rejects them.
+    fn load(&mut self, size: usize, origin: usize, segments: Vec<(usize, Vec<T>)>) -> bool {
+        if !self.can_load(size, origin, &segments) {
+            return false;
+        }
 
-        let start = min(start - (start % ROW_SIZE), self.buffer.len() - 1);
-        let end = min(
-            if end % ROW_SIZE == 0 { end } else { end + ROW_SIZE - (end % ROW_SIZE) },
-            self.buffer.len() - 1);
+        self.clear();
+        for (start, cells) in segments {
+            for (offset, value) in cells.into_iter().enumerate() {
+                self.set_value(start + offset, value);
+            }
+        }
 
-        let mut row_start = start;
-        while row_start < end {
-            write!(f, "  {:#08X}:    ", row_start).unwrap();
+        true
+    }
+}
 
-            let mut ascii = String::new();
-            for i in 0..ROW_SIZE {
-                if (row_start + i) >= end {
-                    break;
-                }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TapeKind {
+    /// All cells are allocated at the start.
+    Vec,
+    /// Cells are allocated in pages on the first write.
+    Paged,
+    /// The tape grows on both ends when the pointer leaves it.
+    Infinite,
+}
 
-                let value = self.get_value(row_start + i);
-                write!(f, "{:02X} ", value).unwrap();
+impl TapeKind {
+    pub fn create<T: Cell>(&self, size: usize) -> Box<dyn Tape<T>> {
+        match self {
+            TapeKind::Vec => Box::new(VecBuffer::new(size)),
+            TapeKind::Paged => Box::new(PagedBuffer::new(size)),
+            TapeKind::Infinite => Box::new(DequeBuffer::new(size)),
+        }
+    }
 
-                // Save the ascii char for the output
-                let char_value: char = value.to_char();
-                if char_value.is_alphanumeric() {
-                    ascii.push(char_value);
-                } else {
-                    ascii.push('.');
-                }
+    /// Returns the number of bytes that `create` allocates for the cells.
+    pub fn initial_memory<T: Cell>(&self, size: usize) -> usize {
+        match self {
+            TapeKind::Vec | TapeKind::Infinite => size.max(1).saturating_mul(mem::size_of::<T>()),
+            // The pages are allocated on the first write
+            TapeKind::Paged => 0,
+        }
+    }
 
-                // Print extra space for readability
-                if (i + 1) % SEPARATOR == 0 {
-                    write!(f, " ").unwrap();
-                    ascii.push(' ');
-                }
-            }
+    /// Returns the number of cells after which the pointer reaches the same
+    /// cell again, `None` if the tape grows instead.
+    pub fn wrap_size(&self, size: usize) -> Option<usize> {
+        match self {
+            TapeKind::Vec | TapeKind::Paged => Some(size),
+            TapeKind::Infinite => None,
+        }
+    }
+}
 
-            write!(f, "    {}\n", ascii).unwrap();
+pub struct VecBuffer<T: Cell> {
+    buffer: Vec<T>,
+}
 
-            row_start += ROW_SIZE;
-        }
+impl<T> Tape<T> for VecBuffer<T>
+    where
+        T: Cell
+{
+    fn get_value(&self, index: usize) -> T {
+        self.buffer[index]
+    }
+
+    fn set_value(&mut self, index: usize, value: T) {
+        self.buffer[index] = value;
+    }
+
+    fn size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.buffer.len() * mem::size_of::<T>()
+    }
+
+    fn segments(&self) -> Vec<(usize, Vec<T>)> {
+        vec![(0, self.buffer.clone())]
+    }
 
-        write!(f, "}}")
+    fn clear(&mut self) {
+        for cell in self.buffer.iter_mut() {
+            *cell = T::default();
+        }
     }
 }
 
@@ -81,6 +154,13 @@ impl<T> VecBuffer<T>
             buffer: vec,
         }
     }
+
+    #[cfg(test)]
+    pub(crate) fn from_cells(cells: Vec<T>) -> Self {
+        VecBuffer {
+            buffer: cells,
+        }
+    }
 }
 
 impl<T> Debug for VecBuffer<T>
@@ -88,6 +168,6 @@ impl<T> Debug for VecBuffer<T>
         T: Cell
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        self.write_buffer(f, 0, 128)
+        dump::write_rows(f, self, None, 0, 128, DumpFormat::Hex)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,152 @@
+use crate::cell::Cell;
+use crate::buffer::Tape;
+use std::collections::VecDeque;
+use std::mem;
+
+/// A tape that grows on both ends. Moving the pointer left of the first cell
+/// adds cells in front, the cell where the program started is tracked as the
+/// origin to report negative positions.
+pub struct DequeBuffer<T: Cell> {
+    buffer: VecDeque<T>,
+    origin: usize,
+}
+
+impl<T> DequeBuffer<T>
+    where
+        T: Cell
+{
+    /// Creates a tape with `size` cells to the right of the origin.
+    pub(crate) fn new(size: usize) -> Self {
+        let mut buffer = VecDeque::new();
+        buffer.resize(size.max(1), T::default());
+
+        DequeBuffer {
+            buffer,
+            origin: 0,
+        }
+    }
+}
+
+impl<T> Tape<T> for DequeBuffer<T>
+    where
+        T: Cell
+{
+    fn get_value(&self, index: usize) -> T {
+        self.buffer[index]
+    }
+
+    fn set_value(&mut self, index: usize, value: T) {
+        self.buffer[index] = value;
+    }
+
+    fn size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.buffer.len() * mem::size_of::<T>()
+    }
+
+    fn segments(&self) -> Vec<(usize, Vec<T>)> {
+        vec![(0, self.buffer.iter().copied().collect())]
+    }
+
+    fn clear(&mut self) {
+        for cell in self.buffer.iter_mut() {
+            *cell = T::default();
+        }
+    }
+
+    fn increase_index(&mut self, index: usize, by: usize) -> usize {
+        let new_index = index.saturating_add(by);
+        if new_index >= self.buffer.len() {
+            self.buffer.resize(new_index + 1, T::default());
+        }
+
+        new_index
+    }
+
+    fn decrease_index(&mut self, index: usize, by: usize) -> usize {
+        if by <= index {
+            return index - by;
+        }
+
+        // The new cells are added in front, the indices shift to the right
+        let missing = by - index;
+        for _ in 0..missing {
+            self.buffer.push_front(T::default());
+        }
+        self.origin += missing;
+
+        0
+    }
+
+    fn origin(&self) -> usize {
+        self.origin
+    }
+
+    fn can_load(&self, size: usize, origin: usize, segments: &[(usize, Vec<T>)]) -> bool {
+        // A grown tape is saved with all of its cells, a larger size can only
+        // come from a corrupted snapshot
+        let saved: usize = segments.iter().map(|(_, cells)| cells.len()).sum();
+        size > 0 && origin < size && size <= saved.max(self.size())
+    }
+
+    fn load(&mut self, size: usize, origin: usize, segments: Vec<(usize, Vec<T>)>) -> bool {
+        if !self.can_load(size, origin, &segments) {
+            return false;
+        }
+
+        self.buffer.clear();
+        self.buffer.resize(size, T::default());
+        self.origin = origin;
+        for (start, cells) in segments {
+            for (offset, value) in cells.into_iter().enumerate() {
+                self.buffer[start + offset] = value;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grow() {
+        let mut tape: DequeBuffer<u8> = DequeBuffer::new(2);
+        tape.set_value(0, 1);
+
+        // Grow to the right
+        let index = tape.increase_index(0, 4);
+        assert_eq!(index, 4);
+        assert_eq!(tape.size(), 5);
+        assert_eq!(tape.origin(), 0);
+
+        // Grow to the left
+        let index = tape.decrease_index(index, 7);
+        assert_eq!(index, 0);
+        assert_eq!(tape.size(), 8);
+        assert_eq!(tape.origin(), 3);
+        assert_eq!(tape.get_value(3), 1);
+
+        let index = tape.increase_index(index, 3);
+        assert_eq!(index, 3);
+        assert_eq!(tape.get_value(index), 1);
+    }
+
+    #[test]
+    fn test_load() {
+        let mut tape: DequeBuffer<u8> = DequeBuffer::new(2);
+        tape.decrease_index(0, 2);
+        tape.set_value(1, 5);
+
+        let mut other: DequeBuffer<u8> = DequeBuffer::new(1);
+        assert!(other.load(tape.size(), tape.origin(), tape.segments()));
+        assert_eq!(other.size(), 4);
+        assert_eq!(other.origin(), 2);
+        assert_eq!(other.get_value(1), 5);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::cell::Cell;
+use crate::buffer::Tape;
+use std::collections::HashMap;
+use std::mem;
+
+const PAGE_SIZE: usize = 4096;
+
+/// A sparse tape that allocates pages on the first write of a non zero value.
+/// This allows huge tapes for programs that scatter their data.
+pub struct PagedBuffer<T: Cell> {
+    pages: HashMap<usize, Vec<T>>,
+    size: usize,
+}
+
+impl<T> PagedBuffer<T>
+    where
+        T: Cell
+{
+    pub(crate) fn new(size: usize) -> Self {
+        PagedBuffer {
+            pages: HashMap::new(),
+            size,
+        }
+    }
+}
+
+impl<T> Tape<T> for PagedBuffer<T>
+    where
+        T: Cell
+{
+    fn get_value(&self, index: usize) -> T {
+        match self.pages.get(&(index / PAGE_SIZE)) {
+            Some(page) => page[index % PAGE_SIZE],
+            None => T::default(),
+        }
+    }
+
+    fn set_value(&mut self, index: usize, value: T) {
+        assert!(index < self.size, "index out of bounds: the size is {} but the index is {}", self.size, index);
+
+        let page = index / PAGE_SIZE;
+        if value == T::default() && !self.pages.contains_key(&page) {
+            return;
+        }
+
+        self.pages.entry(page)
+            .or_insert_with(|| vec![T::default(); PAGE_SIZE])[index % PAGE_SIZE] = value;
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.pages.len() * PAGE_SIZE * mem::size_of::<T>()
+    }
+
+    fn segments(&self) -> Vec<(usize, Vec<T>)> {
+        let mut pages: Vec<usize> = self.pages.keys().copied().collect();
+        pages.sort_unstable();
+
+        pages.into_iter()
+            .map(|page| {
+                let start = page * PAGE_SIZE;
+                let len = PAGE_SIZE.min(self.size - start);
+                (start, self.pages[&page][..len].to_vec())
+            })
+            .collect()
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate_on_write() {
+        let mut tape: PagedBuffer<u16> = PagedBuffer::new(1 << 40);
+        assert_eq!(tape.memory_usage(), 0);
+        assert_eq!(tape.get_value(1 << 39), 0);
+
+        // Writing 0 doesn't allocate
+        tape.set_value(1 << 39, 0);
+        assert_eq!(tape.memory_usage(), 0);
+
+        tape.set_value(1 << 39, 7);
+        tape.set_value((1 << 39) + 1, 8);
+        tape.set_value(3, 9);
+        assert_eq!(tape.memory_usage(), 2 * PAGE_SIZE * 2);
+        assert_eq!(tape.get_value(1 << 39), 7);
+        assert_eq!(tape.get_value((1 << 39) + 1), 8);
+        assert_eq!(tape.get_value(3), 9);
+        assert_eq!(tape.get_value(4), 0);
+
+        let segments = tape.segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].0, 0);
+        assert_eq!(segments[1].0, 1 << 39);
+
+        tape.clear();
+        assert_eq!(tape.memory_usage(), 0);
+        assert_eq!(tape.get_value(3), 0);
+    }
+
+    #[test]
+    fn test_last_page() {
+        let mut tape: PagedBuffer<u8> = PagedBuffer::new(PAGE_SIZE + 10);
+        tape.set_value(PAGE_SIZE + 9, 1);
+
+        let segments = tape.segments();
+        assert_eq!(segments, vec![(PAGE_SIZE, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 1])]);
+    }
+}
@@ -1,6 +1,7 @@
 use std::num::Wrapping;
 use std::fmt::{Debug, UpperHex};
 use std::ops::{Sub, Add};
+use std::convert::TryFrom;
 
 pub trait Cell:
     'static +
@@ -15,12 +16,18 @@ pub trait Cell:
     fn sub_overflow(&self, other: &Self) -> Self;
 
     fn to_char(&self) -> char;
+
+    fn to_usize(&self) -> usize;
+
+    /// Creates a cell from the lower bytes of the value.
+    fn from_usize(value: usize) -> Self;
 }
 
 pub trait IntCell: Cell {}
 
 impl<T: IntCell> Cell for T
     where
+        T: Into<u32> + TryFrom<u32>,
         Wrapping<T>: Add<Output=Wrapping<T>> + Sub<Output=Wrapping<T>>
 {
     fn add_overflow(&self, other: &Self) -> Self {
@@ -41,6 +48,17 @@ impl<T: IntCell> Cell for T
 
         char::from(data[0])
     }
+
+    fn to_usize(&self) -> usize {
+        let value: u32 = (*self).into();
+        value as usize
+    }
+
+    fn from_usize(value: usize) -> Self {
+        let bits = ::std::mem::size_of::<Self>() * 8;
+        let mask = if bits >= 32 { u32::MAX } else { (1u32 << bits) - 1 };
+        Self::try_from(value as u32 & mask).unwrap_or_default()
+    }
 }
 
 impl IntCell for u8 {}
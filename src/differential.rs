@@ -0,0 +1,223 @@
+//! Differential tests between the optimization levels. A program has to
+//! produce the same output and the same memory with every level and pass.
+
+use crate::Instruction;
+use crate::buffer::TapeKind;
+use crate::fuzzing::Random;
+use crate::interpreter::{Interpreter, Limits};
+use crate::operations::io::file_io::{FileAccess, FileIo};
+use crate::operations::io::test_io::TestIo;
+use crate::parser::{Parser, ParserMode, Pass};
+use crate::snapshot::BankState;
+use std::collections::BTreeMap;
+
+/// The tapes the programs run on. The generated programs move the pointer
+/// further than the small tapes are long, so it wraps around them.
+const TAPES: [(TapeKind, usize); 5] = [
+    (TapeKind::Vec, 4),
+    (TapeKind::Vec, 5),
+    (TapeKind::Paged, 7),
+    (TapeKind::Vec, 16),
+    (TapeKind::Infinite, 2),
+];
+const MAX_OFFSET: isize = 8;
+/// Loops are nested at most this deep, every loop runs at most 255 times.
+const MAX_LOOP_DEPTH: usize = 2;
+const MAX_STEPS: u64 = 10_000_000;
+const INPUT: [u8; 5] = [3, 0, 200, 17, 1];
+
+/// The output and the memory at the end of a run.
+#[derive(Debug, PartialEq)]
+struct RunResult {
+    output: Vec<u8>,
+    banks: Vec<Memory>,
+}
+
+/// The non zero cells and the pointer of a bank, relative to the start
+/// position. The infinite tape grows differently with every mode.
+#[derive(Debug, PartialEq)]
+struct Memory {
+    cells: BTreeMap<isize, u8>,
+    pointer: isize,
+}
+
+impl From<BankState<u8>> for Memory {
+    fn from(bank: BankState<u8>) -> Self {
+        let origin = bank.origin as isize;
+        let cells = bank.segments.into_iter()
+            .flat_map(|(start, cells)| cells.into_iter().enumerate()
+                .map(move |(index, cell)| ((start + index) as isize - origin, cell)))
+            .filter(|(_, cell)| *cell != 0)
+            .collect();
+
+        Memory {
+            cells,
+            pointer: bank.index as isize - origin,
+        }
+    }
+}
+
+fn run(code: &str, mode: ParserMode, tape: TapeKind, size: usize) -> RunResult {
+    let instructions: Vec<Instruction<u8>> = Parser::new(mode, tape, size).parse_script(code);
+    let (io, output) = TestIo::recording(INPUT.to_vec(), true);
+    let mut file_io = FileIo::with_files(Vec::new());
+    file_io.set_access(FileAccess::Disabled).unwrap();
+
+    let mut interpreter = Interpreter::new_with_io(
+        &instructions,
+        tape.create(size),
+        Box::new(io),
+        Box::new(file_io));
+    interpreter.set_limits(Limits {
+        max_steps: Some(MAX_STEPS),
+        ..Limits::default()
+    });
+
+    if let Err(error) = interpreter.run() {
+        panic!("The program {:?} failed with {} on {:?}({}): {}", code, mode, tape, size, error);
+    }
+
+    let output = output.lock().unwrap().clone();
+    RunResult {
+        output,
+        banks: interpreter.snapshot().banks.into_iter().map(Memory::from).collect(),
+    }
+}
+
+/// Returns the modes that are compared: every level and every single pass.
+fn modes() -> Vec<ParserMode> {
+    (0..=ParserMode::MAX_LEVEL).map(ParserMode::level)
+        .chain(Pass::ALL.iter().map(|pass| ParserMode::DEBUG.with_pass(*pass, true)))
+        .collect()
+}
+
+/// Asserts that the program behaves the same with all modes on every tape.
+pub fn assert_equivalent(code: &str) {
+    for (tape, size) in TAPES.iter().copied() {
+        assert_equivalent_on(code, tape, size);
+    }
+}
+
+/// Asserts that the program behaves the same with all modes on the tape.
+pub fn assert_equivalent_on(code: &str, tape: TapeKind, size: usize) {
+    let expected = run(code, ParserMode::DEBUG, tape, size);
+    for mode in modes() {
+        assert_eq!(run(code, mode, tape, size), expected,
+            "The program {:?} differs with {} on {:?}({})", code, mode, tape, size);
+    }
+}
+
+/// Generates random programs that always terminate on the tape. A loop ends
+/// with a `-` on its counter cell and the body doesn't change the counter
+/// cells of the enclosing loops. Clear loops like `[-]` can be used on any
+/// cell.
+pub struct ProgramGenerator {
+    random: Random,
+    /// The size of the tape if it wraps around, the offsets of the same cell
+    /// differ by it.
+    wrap: Option<usize>,
+}
+
+impl ProgramGenerator {
+    pub fn new(seed: u64, wrap: Option<usize>) -> Self {
+        ProgramGenerator {
+            random: Random::new(seed),
+            wrap,
+        }
+    }
+
+    fn is_same_cell(&self, first: isize, second: isize) -> bool {
+        match self.wrap {
+            Some(size) => (first - second).rem_euclid(size as isize) == 0,
+            None => first == second,
+        }
+    }
+
+    pub fn generate(&mut self, len: usize) -> String {
+        let mut code = String::new();
+        let mut offset = 0;
+        self.block(&mut code, len, &mut offset, &mut Vec::new());
+        code
+    }
+
+    /// Writes `len` operations. `counters` are the offsets of the counter
+    /// cells of the enclosing loops.
+    fn block(&mut self, code: &mut String, len: usize, offset: &mut isize, counters: &mut Vec<isize>) {
+        for _ in 0..len {
+            let free = !counters.iter().any(|counter| self.is_same_cell(*counter, *offset));
+            match self.random.next(12) {
+                0 | 1 if *offset < MAX_OFFSET => {
+                    code.push('>');
+                    *offset += 1;
+                },
+                2 | 3 if *offset > -MAX_OFFSET => {
+                    code.push('<');
+                    *offset -= 1;
+                },
+                4 | 5 if free => code.push('+'),
+                6 if free => code.push('-'),
+                7 => code.push('.'),
+                8 if free => code.push(','),
+                9 if free => code.push_str("[-]"),
+                10 if free && counters.len() < MAX_LOOP_DEPTH => {
+                    let start = *offset;
+                    code.push('[');
+                    counters.push(start);
+                    let body = self.random.next(8);
+                    self.block(code, body, offset, counters);
+                    counters.pop();
+
+                    // Return to the counter cell
+                    while *offset > start {
+                        code.push('<');
+                        *offset -= 1;
+                    }
+                    while *offset < start {
+                        code.push('>');
+                        *offset += 1;
+                    }
+                    code.push_str("-]");
+                },
+                11 => code.push(['_', 'x', ' '][self.random.next(3)]),
+                _ => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_examples() {
+        assert_equivalent("++++++++++[>+>+++>+++++++>++++++++++<<<<-]>>>++.>+.+++++++..+++.<<++.>+++++++++++++++.>.+++.------.--------.<<+.");
+        assert_equivalent("+++[-]>+<[->+<]>.,[.-]");
+        assert_equivalent(",+-<>[-]>[+]<.,[->++<]>.");
+        // The output depends on the input after a read
+        assert_equivalent("++.,[>+++<-]>.");
+        // The pointer reaches the first cell again on the small tapes
+        assert_equivalent("+>>>>+.");
+        assert_equivalent("+>>>>[.[-]]");
+        assert_equivalent("+<<<<<[-]<<+>[.>]");
+    }
+
+    #[test]
+    fn test_generator() {
+        let mut generator = ProgramGenerator::new(1, None);
+        let code = generator.generate(200);
+        assert_eq!(code.matches('[').count(), code.matches(']').count());
+        assert_eq!(ProgramGenerator::new(1, None).generate(200), code);
+    }
+
+    #[test]
+    fn test_random_programs() {
+        for (tape, size) in TAPES.iter().copied() {
+            let mut generator = ProgramGenerator::new(0x5eed + size as u64, tape.wrap_size(size));
+            for _ in 0..100 {
+                let len = 10 + generator.random.next(60);
+                assert_equivalent_on(&generator.generate(len), tape, size);
+            }
+        }
+    }
+}
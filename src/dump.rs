@@ -0,0 +1,293 @@
+use crate::Cell;
+use crate::buffer::Tape;
+use std::fmt::{self, Write as FmtWrite};
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+
+const ROW_SIZE: usize = 32;
+const SEPARATOR: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpFormat {
+    Hex,
+    Dec,
+    Ascii,
+    Json,
+    /// The little endian bytes of the cells. This can only be written to a file.
+    Raw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpRange {
+    /// The cells from `start` to `end` (exclusive). The positions are relative
+    /// to the cell where the program started and can be negative.
+    Range(isize, isize),
+    /// The cells that are at most `n` cells away from the data pointer.
+    AroundPointer(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpSettings {
+    pub format: DumpFormat,
+    pub range: DumpRange,
+    /// The dump is written to this file instead of stdout.
+    pub file: Option<String>,
+}
+
+impl Default for DumpSettings {
+    fn default() -> Self {
+        DumpSettings {
+            format: DumpFormat::Hex,
+            range: DumpRange::Range(0, 128),
+            file: None,
+        }
+    }
+}
+
+impl DumpRange {
+    /// Returns the buffer indices of the range clamped to the buffer size.
+    fn resolve(&self, size: usize, origin: usize, pointer: usize) -> (usize, usize) {
+        let (start, end) = match *self {
+            DumpRange::Range(start, end) => (to_index(start, origin), to_index(end, origin)),
+            DumpRange::AroundPointer(n) => (pointer.saturating_sub(n), pointer.saturating_add(n).saturating_add(1)),
+        };
+
+        let end = end.min(size);
+        (start.min(end), end)
+    }
+}
+
+/// Converts a position relative to the origin to a buffer index. Positions in
+/// front of the buffer are clamped to `0`.
+fn to_index(position: isize, origin: usize) -> usize {
+    if position < 0 {
+        origin.saturating_sub(position.unsigned_abs())
+    } else {
+        origin.saturating_add(position as usize)
+    }
+}
+
+/// Converts a buffer index to a position relative to the origin.
+fn to_position(index: usize, origin: usize) -> isize {
+    (index as isize).wrapping_sub(origin as isize)
+}
+
+/// Writes the dump of the banks as defined by the settings. Every bank is
+/// given as `(buffer, pointer)`, the cell at the pointer is marked in the text
+/// formats. The bank labels are only written if there is more than one bank.
+pub fn dump<T: Cell>(banks: &[(&dyn Tape<T>, usize)], active: usize, settings: &DumpSettings) -> io::Result<()> {
+    if settings.format == DumpFormat::Raw && settings.file.is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the raw format requires a dump file"));
+    }
+
+    let labeled = banks.len() > 1;
+    let mut data = Vec::new();
+    let mut text = String::new();
+    if labeled && settings.format == DumpFormat::Json {
+        write!(text, "{{\"active\": {}, \"banks\": [", active).unwrap();
+    }
+
+    for (bank, &(buffer, pointer)) in banks.iter().enumerate() {
+        let (start, end) = settings.range.resolve(buffer.size(), buffer.origin(), pointer);
+
+        match settings.format {
+            DumpFormat::Raw => {
+                for index in start..end {
+                    let value = buffer.get_value(index).to_usize().to_le_bytes();
+                    data.extend_from_slice(&value[..mem::size_of::<T>()]);
+                }
+            },
+            DumpFormat::Json => {
+                if bank != 0 {
+                    text.push_str(", ");
+                }
+                write_json(&mut text, buffer, pointer, start, end).unwrap();
+            },
+            format => {
+                if labeled {
+                    let marker = if bank == active { " (active)" } else { "" };
+                    writeln!(text, "Bank {}{}:", bank, marker).unwrap();
+                }
+                write_rows(&mut text, buffer, Some(pointer), start, end, format).unwrap();
+                text.push('\n');
+            },
+        }
+    }
+
+    if settings.format == DumpFormat::Json {
+        if labeled {
+            text.push_str("]}");
+        }
+        text.push('\n');
+    }
+    data.extend_from_slice(text.as_bytes());
+
+    match &settings.file {
+        Some(file_name) => File::create(file_name)?.write_all(&data),
+        None => io::stdout().write_all(&data),
+    }
+}
+
+fn write_json<T: Cell, W: FmtWrite>(
+    f: &mut W,
+    buffer: &dyn Tape<T>,
+    pointer: usize,
+    start: usize,
+    end: usize) -> fmt::Result
+{
+    let origin = buffer.origin();
+    write!(
+        f,
+        "{{\"pointer\": {}, \"start\": {}, \"end\": {}, \"cells\": [",
+        to_position(pointer, origin),
+        to_position(start, origin),
+        to_position(end, origin))?;
+    for index in start..end {
+        if index != start {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", buffer.get_value(index).to_usize())?;
+    }
+    write!(f, "]}}")
+}
+
+/// Writes the cells from `start` to `end` in rows of `ROW_SIZE` cells. The
+/// range is clamped to the buffer. Rows are labeled with their position
+/// relative to the origin of the buffer.
+pub fn write_rows<T: Cell, W: FmtWrite>(
+    f: &mut W,
+    buffer: &dyn Tape<T>,
+    pointer: Option<usize>,
+    start: usize,
+    end: usize,
+    format: DumpFormat) -> fmt::Result
+{
+    let end = end.min(buffer.size());
+
+    let dec_width = format!("{}", T::from_usize(usize::MAX).to_usize()).len();
+
+    writeln!(f, "Buffer {{")?;
+
+    let mut row_start = start;
+    while row_start < end {
+        let position = to_position(row_start, buffer.origin());
+        if position < 0 {
+            write!(f, " -{:#08X}:   ", position.unsigned_abs())?;
+        } else {
+            write!(f, "  {:#08X}:   ", position)?;
+        }
+
+        let mut ascii = String::new();
+        for index in row_start..(row_start + ROW_SIZE).min(end) {
+            let marker = if Some(index) == pointer { '>' } else { ' ' };
+            let value = buffer.get_value(index);
+
+            // Save the ascii char for the output
+            let char_value: char = value.to_char();
+            let char_value = if char_value.is_alphanumeric() { char_value } else { '.' };
+            ascii.push(char_value);
+
+            match format {
+                DumpFormat::Dec => write!(f, "{}{:width$}", marker, value.to_usize(), width = dec_width)?,
+                DumpFormat::Ascii => write!(f, "{}{}", marker, char_value)?,
+                _ => write!(f, "{}{:0width$X}", marker, value, width = mem::size_of::<T>() * 2)?,
+            }
+
+            // Print extra space for readability
+            if (index - row_start + 1).is_multiple_of(SEPARATOR) {
+                write!(f, " ")?;
+                ascii.push(' ');
+            }
+        }
+
+        if format == DumpFormat::Ascii {
+            writeln!(f)?;
+        } else {
+            writeln!(f, "   {}", ascii)?;
+        }
+
+        row_start = row_start.saturating_add(ROW_SIZE);
+    }
+
+    write!(f, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::buffer::{DequeBuffer, VecBuffer};
+
+    fn create_buffer(size: usize) -> VecBuffer<u8> {
+        VecBuffer::from_cells((0..size).map(|x| x as u8).collect())
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(DumpRange::Range(0, 128).resolve(30, 0, 0), (0, 30));
+        assert_eq!(DumpRange::Range(40, 128).resolve(30, 0, 0), (30, 30));
+        assert_eq!(DumpRange::Range(0, 128).resolve(0, 0, 0), (0, 0));
+        assert_eq!(DumpRange::AroundPointer(2).resolve(30, 0, 1), (0, 4));
+        assert_eq!(DumpRange::AroundPointer(2).resolve(30, 0, 29), (27, 30));
+        assert_eq!(DumpRange::AroundPointer(usize::MAX).resolve(30, 0, 5), (0, 30));
+
+        // Negative positions are in front of the origin
+        assert_eq!(DumpRange::Range(-3, 2).resolve(30, 5, 0), (2, 7));
+        assert_eq!(DumpRange::Range(-10, 2).resolve(30, 5, 0), (0, 7));
+    }
+
+    #[test]
+    fn test_rows_include_last_cell() {
+        let buffer = create_buffer(33);
+        let mut text = String::new();
+        write_rows(&mut text, &buffer, Some(32), 0, 33, DumpFormat::Hex).unwrap();
+
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert!(rows[1].starts_with("  0x000000:    00 01"));
+        assert!(rows[2].starts_with("  0x000020:   >20   "));
+
+        // The rows start at the range
+        let mut text = String::new();
+        write_rows(&mut text, &buffer, None, 30, 40, DumpFormat::Hex).unwrap();
+
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].starts_with("  0x00001E:    1E 1F 20   "));
+    }
+
+    #[test]
+    fn test_rows_empty_buffer() {
+        let buffer = create_buffer(0);
+        let mut text = String::new();
+        write_rows(&mut text, &buffer, None, 0, 128, DumpFormat::Hex).unwrap();
+
+        assert_eq!(text, "Buffer {\n}");
+    }
+
+    #[test]
+    fn test_rows_negative_positions() {
+        let mut buffer: DequeBuffer<u8> = DequeBuffer::new(4);
+        buffer.decrease_index(0, 2);
+
+        let mut text = String::new();
+        write_rows(&mut text, &buffer, Some(2), 0, 6, DumpFormat::Hex).unwrap();
+
+        let rows: Vec<&str> = text.lines().collect();
+        assert!(rows[1].starts_with(" -0x000002:    00 00>00"));
+
+        let mut text = String::new();
+        write_json(&mut text, &buffer, 2, 1, 3).unwrap();
+        assert_eq!(text, "{\"pointer\": 0, \"start\": -1, \"end\": 1, \"cells\": [0, 0]}");
+    }
+
+    #[test]
+    fn test_json() {
+        let buffer = create_buffer(5);
+        let mut text = String::new();
+        write_json(&mut text, &buffer, 2, 1, 4).unwrap();
+
+        assert_eq!(text, "{\"pointer\": 2, \"start\": 1, \"end\": 4, \"cells\": [1, 2, 3]}");
+    }
+}
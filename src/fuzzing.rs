@@ -0,0 +1,195 @@
+//! The entry points of the fuzz targets in `fuzz/`. Every function accepts
+//! arbitrary bytes and panics only if an invariant of the crate is broken.
+
+use crate::buffer::TapeKind;
+use crate::interpreter::{ExecutionError, Interpreter, Limits};
+use crate::operations::io::file_io::{FileAccess, FileIo};
+use crate::operations::io::stream_io::StreamIo;
+use crate::parser::{find_deep_block, printer, EmitFormat, Lexer, Parser, ParserMode, MAX_NESTING};
+use crate::{Cell, Instruction};
+use std::io::{self, Cursor};
+
+const MAX_STEPS: u64 = 10_000;
+const MAX_DEPTH: usize = 64;
+const BANKS: usize = 2;
+const TAPE_KINDS: [TapeKind; 3] = [TapeKind::Vec, TapeKind::Paged, TapeKind::Infinite];
+const FORMATS: [EmitFormat; 3] = [EmitFormat::Tokens, EmitFormat::Ir, EmitFormat::Bytecode];
+
+/// Decodes the bytes as code. Code that is nested too deep is rejected like
+/// the command line does.
+fn decode(data: &[u8]) -> Option<String> {
+    let code = String::from_utf8_lossy(data).into_owned();
+    match find_deep_block(&code, MAX_NESTING) {
+        Some(_) => None,
+        None => Some(code),
+    }
+}
+
+/// Lexes the code and checks that the printed tokens are lexed to the same
+/// instructions.
+pub fn lex(data: &[u8]) {
+    let code = match decode(data) {
+        Some(code) => code,
+        None => return,
+    };
+
+    let mut lexer: Lexer<u8> = Lexer::new();
+    let tokens = lexer.lex_string(&code);
+    for format in FORMATS.iter() {
+        printer::emit(&tokens, *format);
+    }
+
+    let printed = printer::print_tokens(&tokens);
+    let instructions = lexer.flatten(tokens);
+    check_jumps(&instructions);
+    let relexed = lexer.lex_string(&printed);
+    assert_eq!(lexer.flatten(relexed), instructions, "The printed code differs: {:?}", printed);
+}
+
+/// Runs the passes of every optimization level.
+pub fn optimize(data: &[u8]) {
+    let code = match decode(data) {
+        Some(code) => code,
+        None => return,
+    };
+
+    for level in 0..=ParserMode::MAX_LEVEL {
+        optimize_with::<u8>(&code, ParserMode::level(level), TapeKind::Infinite);
+    }
+    optimize_with::<u32>(&code, ParserMode::ALL, TapeKind::Vec);
+}
+
+fn optimize_with<T: Cell>(code: &str, mode: ParserMode, tape: TapeKind) {
+    let mut parser: Parser<T> = Parser::new(mode, tape, 16);
+    let tokens = parser.parse_tokens(code);
+    for format in FORMATS.iter() {
+        printer::emit(&tokens, *format);
+    }
+    check_jumps(&parser.flatten(tokens));
+}
+
+/// Executes the code with a step limit. The first byte selects the tape and
+/// its size, the code is also used as the input.
+pub fn execute(data: &[u8]) {
+    let (config, code) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let code = match decode(code) {
+        Some(code) => code,
+        None => return,
+    };
+
+    let tape = TAPE_KINDS[*config as usize % TAPE_KINDS.len()];
+    let size = 1 + (*config as usize >> 2) % 16;
+    execute_with::<u8>(&code, data, ParserMode::DEBUG, tape, size);
+    execute_with::<u8>(&code, data, ParserMode::ALL, tape, size);
+    execute_with::<u16>(&code, data, ParserMode::ALL, tape, size);
+}
+
+fn execute_with<T: Cell>(code: &str, input: &[u8], mode: ParserMode, tape: TapeKind, size: usize) {
+    let instructions: Vec<Instruction<T>> = Parser::new(mode, tape, size).parse_script(code);
+    let mut file_io = FileIo::with_files(Vec::new());
+    file_io.set_access(FileAccess::Disabled).unwrap();
+
+    let mut interpreter = Interpreter::new_with_io(
+        &instructions,
+        tape.create(size),
+        Box::new(StreamIo::new(Cursor::new(input.to_vec()), io::sink())),
+        Box::new(file_io));
+    interpreter.set_limits(Limits {
+        max_steps: Some(MAX_STEPS),
+        max_depth: Some(MAX_DEPTH),
+        ..Limits::default()
+    });
+    for _ in 1..BANKS {
+        interpreter.add_bank(tape.create(size));
+    }
+
+    // Limits and undefined procedures are expected, an invalid jump is a parser bug
+    if let Err(error) = interpreter.run() {
+        assert!(!matches!(error.error, ExecutionError::InvalidJump(_)), "The code {:?} failed with {}: {}", code, mode, error);
+    }
+}
+
+/// Asserts that every jump of the flattened code ends at its counterpart.
+fn check_jumps<T: Cell>(instructions: &[Instruction<T>]) {
+    for (index, inst) in instructions.iter().enumerate() {
+        let valid = match inst {
+            Instruction::LoopStart(x) => matches!(instructions.get(index + x), Some(Instruction::LoopEnd(y)) if x == y),
+            Instruction::LoopEnd(x) => index.checked_sub(*x)
+                .is_some_and(|start| matches!(instructions[start], Instruction::LoopStart(y) if *x == y)),
+            Instruction::ProcedureStart(x) => matches!(instructions.get(index + x), Some(Instruction::ProcedureEnd)),
+            _ => true,
+        };
+        assert!(valid, "The instruction {:?} at {} jumps to the wrong instruction", inst, index);
+    }
+}
+
+/// A linear congruential generator for random inputs that are the same in
+/// every run.
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random {
+            state: seed,
+        }
+    }
+
+    /// Returns a number below `bound`.
+    pub fn next(&mut self, bound: usize) -> usize {
+        self.state = self.state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        ((self.state >> 33) % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALPHABET: &[u8] = b"+-<>[],.;:#&=|()%_ x\0";
+
+    /// Inputs that broke the lexer or the interpreter.
+    const REGRESSIONS: [&[u8]; 8] = [
+        b"]",
+        b"[]]",
+        b"+\0[-]",
+        b"())",
+        b"([)]",
+        b"\xff+[>+<-]\xc3(",
+        b"\x03+[<]",
+        b"+(%)%",
+    ];
+
+    fn run_all(data: &[u8]) {
+        lex(data);
+        optimize(data);
+        execute(data);
+    }
+
+    #[test]
+    fn test_regressions() {
+        for data in REGRESSIONS.iter() {
+            run_all(data);
+        }
+
+        let deep = format!("+{}", "[".repeat(MAX_NESTING + 1));
+        run_all(deep.as_bytes());
+    }
+
+    #[test]
+    fn test_random_inputs() {
+        let mut random = Random::new(0xf022);
+        for _ in 0..500 {
+            let len = random.next(48);
+            let data: Vec<u8> = (0..len).map(|_| ALPHABET[random.next(ALPHABET.len())]).collect();
+            run_all(&data);
+        }
+    }
+}
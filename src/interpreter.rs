@@ -1,73 +1,453 @@
-use crate::operations::io::BraindamageIo;
+use crate::operations::io::{BraindamageIo, BraindamageFileIo};
 
 //    +,-
 //   {^.°}
 //   <[@]>
 //    : ;
 
-use crate::buffer::VecBuffer;
+use crate::buffer::Tape;
 use crate::{Instruction, Cell};
-use crate::operations::io::console_io::ConsoleIo;
-use crate::operations::io::file_io::FileIo;
-use std::num::Wrapping;
+use std::time::{Duration, Instant};
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{Error, ErrorKind};
+use crate::snapshot::BankState;
+pub use crate::snapshot::Snapshot;
+use crate::dump::{self, DumpSettings};
+
+/// The timeout and the pause flag are only checked every few steps to keep the loop fast.
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// Limits for the execution. `None` means unlimited.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    /// The maximum number of bytes that the memory buffers of all banks can allocate.
+    pub max_memory: Option<usize>,
+    /// The maximum number of nested procedure calls.
+    pub max_depth: Option<usize>,
+}
+
+/// The state of the interpreter when the execution was aborted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AbortState {
+    pub steps: u64,
+    pub index: usize,
+    pub inst_ptr: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionError {
+    StepLimit(AbortState),
+    Timeout(AbortState),
+    Paused(AbortState),
+    MemoryLimit {
+        required: usize,
+        limit: usize,
+    },
+    RecursionLimit(AbortState),
+    UndefinedProcedure {
+        procedure: usize,
+        state: AbortState,
+    },
+    /// A jump of a block instruction leaves the program.
+    InvalidJump(AbortState),
+}
+
+impl ExecutionError {
+    /// Returns `true` if the execution can be continued from the state at the abort.
+    pub fn is_resumable(&self) -> bool {
+        !matches!(self, ExecutionError::MemoryLimit { .. }
+            | ExecutionError::UndefinedProcedure { .. }
+            | ExecutionError::InvalidJump(_))
+    }
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::StepLimit(state) => write!(
+                f, "The step limit was reached after {} steps (index: {}, instruction: {})",
+                state.steps, state.index, state.inst_ptr),
+            ExecutionError::Timeout(state) => write!(
+                f, "The timeout was reached after {} steps (index: {}, instruction: {})",
+                state.steps, state.index, state.inst_ptr),
+            ExecutionError::Paused(state) => write!(
+                f, "The execution was paused after {} steps (index: {}, instruction: {})",
+                state.steps, state.index, state.inst_ptr),
+            ExecutionError::MemoryLimit { required, limit } => write!(
+                f, "The memory buffer requires {} bytes but the limit is {} bytes",
+                required, limit),
+            ExecutionError::RecursionLimit(state) => write!(
+                f, "The procedure call depth limit was reached after {} steps (index: {}, instruction: {})",
+                state.steps, state.index, state.inst_ptr),
+            ExecutionError::UndefinedProcedure { procedure, state } => write!(
+                f, "The procedure {} is not defined (index: {}, instruction: {})",
+                procedure, state.index, state.inst_ptr),
+            ExecutionError::InvalidJump(state) => write!(
+                f, "The instruction jumps outside of the program (index: {}, instruction: {})",
+                state.index, state.inst_ptr),
+        }
+    }
+}
+
+/// Statistics about an execution. The IO counts are counted in cells.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RunStats {
+    pub steps: u64,
+    pub max_index: usize,
+    pub final_index: usize,
+    pub console_reads: u64,
+    pub console_writes: u64,
+    pub file_reads: u64,
+    pub file_writes: u64,
+}
+
+impl Display for RunStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "steps:          {}", self.steps)?;
+        writeln!(f, "max index:      {}", self.max_index)?;
+        writeln!(f, "final index:    {}", self.final_index)?;
+        writeln!(f, "console reads:  {}", self.console_reads)?;
+        writeln!(f, "console writes: {}", self.console_writes)?;
+        writeln!(f, "file reads:     {}", self.file_reads)?;
+        write!(f, "file writes:    {}", self.file_writes)
+    }
+}
+
+/// An execution error with the statistics up to the abort.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub error: ExecutionError,
+    pub stats: RunStats,
+}
+
+impl RuntimeError {
+    /// The process exit code for this error. Every error kind has a distinct code.
+    pub fn exit_code(&self) -> i32 {
+        match self.error {
+            ExecutionError::StepLimit(_) => 2,
+            ExecutionError::Timeout(_) => 3,
+            ExecutionError::MemoryLimit { .. } => 4,
+            ExecutionError::Paused(_) => 5,
+            ExecutionError::RecursionLimit(_) => 6,
+            ExecutionError::UndefinedProcedure { .. } => 7,
+            ExecutionError::InvalidJump(_) => 8,
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+/// A memory bank that is not active.
+struct Bank<T: Cell> {
+    buffer: Box<dyn Tape<T>>,
+    index: usize,
+}
 
 pub struct Interpreter<'a, T: Cell> {
-    buffer: VecBuffer<T>,
+    // The active bank is kept in `buffer` and `index`, its slot in `banks` is empty
+    buffer: Box<dyn Tape<T>>,
     index: usize,
+    banks: Vec<Option<Bank<T>>>,
+    bank: usize,
 
     instructions: &'a Vec<Instruction<T>>,
+    inst_ptr: usize,
+    /// The position of the `ProcedureStart` of every defined procedure.
+    procedures: HashMap<usize, usize>,
+    /// The positions of the active calls.
+    call_stack: Vec<usize>,
 
     console_io: Box<dyn BraindamageIo<T>>,
-    file_io: Box<dyn BraindamageIo<T>>,
+    file_io: Box<dyn BraindamageFileIo<T>>,
+
+    limits: Limits,
+    /// The memory usage of the active tape at the last memory check.
+    checked_memory: usize,
+    stats: RunStats,
+    paused: Arc<AtomicBool>,
 }
 
 impl<'a, T> Interpreter<'a, T>
     where T: Cell
 {
-    const DEFAULT_FILE: &'static str = "bd_data.txt";
-
-    pub fn new(instructions: &'a Vec<Instruction<T>>, buffer_size: usize) -> Self {
-        Self::new_with_io(
-            instructions,
-            buffer_size,
-            Box::new(ConsoleIo::new()),
-            Box::new(FileIo::new(&Self::DEFAULT_FILE))
-        )
-    }
-
     pub fn new_with_io(
         instructions: &'a Vec<Instruction<T>>,
-        buffer_size: usize,
+        buffer: Box<dyn Tape<T>>,
         console_io: Box<dyn BraindamageIo<T>>,
-        file_io: Box<dyn BraindamageIo<T>>) -> Self
+        file_io: Box<dyn BraindamageFileIo<T>>) -> Self
     {
         Interpreter {
-            buffer: VecBuffer::new(buffer_size),
+            buffer,
             index: 0,
+            banks: vec![None],
+            bank: 0,
 
             instructions,
+            inst_ptr: 0,
+            procedures: HashMap::new(),
+            call_stack: Vec::new(),
 
             console_io,
-            file_io
+            file_io,
+
+            limits: Limits::default(),
+            checked_memory: 0,
+            stats: RunStats::default(),
+            paused: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Adds a memory bank that can be selected with `|`. The bank of
+    /// `new_with_io` is the bank 0.
+    pub fn add_bank(&mut self, buffer: Box<dyn Tape<T>>) {
+        self.banks.push(Some(Bank {
+            buffer,
+            index: 0,
+        }));
+    }
+
+    /// Makes `bank` the active bank. Returns `false` if the bank doesn't exist.
+    fn switch_bank(&mut self, bank: usize) -> bool {
+        if bank >= self.banks.len() {
+            return false;
+        }
+        if bank == self.bank {
+            return true;
+        }
+
+        let next = self.banks[bank].take().unwrap();
+        self.banks[self.bank] = Some(Bank {
+            buffer: std::mem::replace(&mut self.buffer, next.buffer),
+            index: std::mem::replace(&mut self.index, next.index),
+        });
+        self.bank = bank;
+        // The memory is checked again with the new tape
+        self.checked_memory = 0;
+
+        true
+    }
+
+    /// Sets the current cell of `bank`.
+    fn set_bank_value(&mut self, bank: usize, value: T) {
+        match &mut self.banks[bank] {
+            Some(slot) => slot.buffer.set_value(slot.index, value),
+            None => self.buffer.set_value(self.index, value),
         }
     }
 
-    pub fn run(&mut self) {
-        self.execute(self.instructions);
+    /// The buffers and indices of all banks in order.
+    fn bank_views(&self) -> Vec<(&dyn Tape<T>, usize)> {
+        self.banks.iter()
+            .enumerate()
+            .map(|(bank, slot)| match slot {
+                Some(slot) => (slot.buffer.as_ref(), slot.index),
+                None => {
+                    debug_assert_eq!(bank, self.bank);
+                    (self.buffer.as_ref(), self.index)
+                },
+            })
+            .collect()
+    }
+
+    /// Runs the program until it finishes or an error occurs. A paused or
+    /// aborted execution continues where it stopped if `run` is called again.
+    pub fn run(&mut self) -> Result<RunStats, RuntimeError> {
+        let result = self.execute(None);
+        self.finish(result)
+    }
+
+    /// Executes at most `n` instructions. Returns `true` if the program has finished.
+    pub fn step(&mut self, n: u64) -> Result<bool, RuntimeError> {
+        let result = self.execute(Some(n));
+        self.finish(result)?;
+        Ok(self.is_finished())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.inst_ptr >= self.instructions.len()
+    }
+
+    /// Pauses a running execution. `run` and `step` return `ExecutionError::Paused`.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// A handle that pauses the execution when it's set to `true`. This can be
+    /// used from other threads and signal handlers.
+    pub fn pause_handle(&self) -> Arc<AtomicBool> {
+        self.paused.clone()
+    }
+
+    /// Writes the buffered console output. Returns the first write error.
+    pub fn flush_output(&mut self) -> Result<(), Error> {
+        self.console_io.flush()
     }
 
-    fn execute(&mut self, instructions: &Vec<Instruction<T>>) {
-        let mut inst_ptr = 0;
+    pub fn snapshot(&self) -> Snapshot<T> {
+        Snapshot {
+            banks: self.bank_views().into_iter()
+                .map(|(buffer, index)| BankState {
+                    size: buffer.size(),
+                    origin: buffer.origin(),
+                    segments: buffer.segments(),
+                    index,
+                })
+                .collect(),
+            bank: self.bank,
+            procedures: {
+                let mut procedures: Vec<(usize, usize)> = self.procedures.iter()
+                    .map(|(procedure, start)| (*procedure, *start))
+                    .collect();
+                procedures.sort_unstable();
+                procedures
+            },
+            call_stack: self.call_stack.clone(),
+            inst_ptr: self.inst_ptr,
+            stats: self.stats,
+            program_hash: self.program_hash(),
+            io_state: self.file_io.save_state(),
+        }
+    }
+
+    /// Restores the state from a snapshot of the same program. The snapshot is
+    /// validated first, nothing is changed if it's rejected.
+    pub fn restore(&mut self, snapshot: Snapshot<T>) -> Result<(), Error> {
+        if snapshot.program_hash != self.program_hash() {
+            return Err(Error::new(ErrorKind::InvalidData, "the snapshot was created by a different program"));
+        }
+        if snapshot.banks.len() != self.banks.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("the snapshot requires {} banks", snapshot.banks.len())));
+        }
+        for (number, (bank, (buffer, _))) in snapshot.banks.iter().zip(self.bank_views()).enumerate() {
+            if !buffer.can_load(bank.size, bank.origin, &bank.segments) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("the snapshot requires a buffer size of {} in bank {}", bank.size, number)));
+            }
+        }
+        let banks_valid = snapshot.banks.iter().all(|bank| {
+            bank.index < bank.size && bank.segments.iter()
+                .all(|(start, cells)| start.checked_add(cells.len()).is_some_and(|end| end <= bank.size))
+        });
+        let positions_valid = snapshot.procedures.iter()
+            .all(|(_, start)| matches!(self.instructions.get(*start), Some(Instruction::ProcedureStart(_))))
+            && snapshot.call_stack.iter()
+            .all(|call| matches!(self.instructions.get(*call), Some(Instruction::CallProcedure)));
+        // Nothing is selected with an empty file table
+        let io_state = &snapshot.io_state;
+        let selection_valid = io_state.selected < io_state.handles.len() || io_state.selected == 0;
+        if !banks_valid || !positions_valid || !selection_valid || snapshot.bank >= snapshot.banks.len()
+            || snapshot.inst_ptr > self.instructions.len()
+        {
+            return Err(Error::new(ErrorKind::InvalidData, "the snapshot is corrupted"));
+        }
+
+        // The file state is checked against the file table before it's applied
+        self.file_io.restore_state(snapshot.io_state)?;
+
+        self.switch_bank(0);
+        for (number, bank) in snapshot.banks.into_iter().enumerate() {
+            let (buffer, index) = match &mut self.banks[number] {
+                Some(slot) => (&mut slot.buffer, &mut slot.index),
+                None => (&mut self.buffer, &mut self.index),
+            };
+            let loaded = buffer.load(bank.size, bank.origin, bank.segments);
+            debug_assert!(loaded, "the bank {} was validated", number);
+            *index = bank.index;
+        }
+        self.switch_bank(snapshot.bank);
+
+        self.procedures = snapshot.procedures.into_iter().collect();
+        self.call_stack = snapshot.call_stack;
+        self.inst_ptr = snapshot.inst_ptr;
+        self.stats = snapshot.stats;
+
+        Ok(())
+    }
+
+    /// FNV-1a hash of the instructions.
+    fn program_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for inst in self.instructions {
+            for byte in format!("{:?}", inst).bytes() {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+
+        hash
+    }
+
+    fn finish(&mut self, result: Result<(), ExecutionError>) -> Result<RunStats, RuntimeError> {
+        self.stats.final_index = self.index;
+
+        match result {
+            Ok(_) => Ok(self.stats),
+            Err(error) => Err(RuntimeError {
+                error,
+                stats: self.stats,
+            }),
+        }
+    }
+
+    /// Executes the instructions until the end or until `budget` steps were executed.
+    fn execute(&mut self, budget: Option<u64>) -> Result<(), ExecutionError> {
+        self.check_memory()?;
+
+        let deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        let instructions = self.instructions;
+
+        // The limits apply to every call, a resumed execution starts with a new budget
+        let mut steps: u64 = 0;
+        let mut inst_ptr = self.inst_ptr;
         while inst_ptr < instructions.len() {
+            if budget.is_some_and(|budget| steps >= budget) {
+                break;
+            }
+            if self.limits.max_steps.is_some_and(|max_steps| steps >= max_steps) {
+                return Err(ExecutionError::StepLimit(self.abort(inst_ptr)));
+            }
+            if steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Err(ExecutionError::Timeout(self.abort(inst_ptr)));
+                }
+                if self.paused.swap(false, Ordering::Relaxed) {
+                    return Err(ExecutionError::Paused(self.abort(inst_ptr)));
+                }
+            }
+            // The tapes only grow, the limit is checked if the active tape allocated memory
+            if self.limits.max_memory.is_some() && self.buffer.memory_usage() > self.checked_memory {
+                self.check_memory()?;
+            }
+            steps += 1;
+            self.stats.steps += 1;
+
             let inst = instructions.get(inst_ptr).unwrap();
 
             match inst {
                 Instruction::NoOp => {},
                 Instruction::IncreaseIndex(x) => {
-                    self.index = (Wrapping(self.index) + Wrapping(*x)).0 % self.buffer.size()
+                    self.index = self.buffer.increase_index(self.index, *x);
+                    self.stats.max_index = self.stats.max_index.max(self.index);
                 },
                 Instruction::DecreaseIndex(x) => {
-                    self.index = (Wrapping(self.index) - Wrapping(*x)).0 % self.buffer.size()
+                    self.index = self.buffer.decrease_index(self.index, *x);
+                    self.stats.max_index = self.stats.max_index.max(self.index);
                 },
                 Instruction::IncreaseValue(x) => {
                     self.buffer.set_value(
@@ -80,12 +460,14 @@ impl<'a, T> Interpreter<'a, T>
                         self.buffer.get_value(self.index).sub_overflow(x))
                 },
                 Instruction::IoRead => {
+                    self.stats.console_reads += 1;
                     self.buffer.set_value(
                         self.index,
                         self.console_io.read()
                     )
                 },
                 Instruction::IoWrite => {
+                    self.stats.console_writes += 1;
                     let value = self.buffer.get_value(self.index);
                     self.buffer.set_value(
                         self.index,
@@ -95,35 +477,482 @@ impl<'a, T> Interpreter<'a, T>
                 Instruction::LoopStart(x) => {
                     let break_value: T = T::default();
                     if self.buffer.get_value(self.index) == break_value {
-                        inst_ptr += *x;
+                        inst_ptr = self.jump(inst_ptr, inst_ptr.checked_add(*x))?;
                     }
                 }
                 Instruction::LoopEnd(x) => {
                     let break_value: T = T::default();
                     if self.buffer.get_value(self.index) != break_value {
-                        inst_ptr -= *x;
+                        inst_ptr = self.jump(inst_ptr, inst_ptr.checked_sub(*x))?;
                     }
                 }
                 Instruction::FileIoRead => {
+                    self.stats.file_reads += 1;
                     self.buffer.set_value(
                         self.index,
                         self.file_io.read()
                     )
                 },
                 Instruction::FileIoWrite => {
+                    self.stats.file_writes += 1;
                     let value = self.buffer.get_value(self.index);
                     self.buffer.set_value(
                         self.index,
                         self.file_io.write(value)
                     )
+                },
+                Instruction::FileSelect => {
+                    let value = self.buffer.get_value(self.index);
+                    self.buffer.set_value(
+                        self.index,
+                        self.file_io.select(value)
+                    )
+                },
+                Instruction::FileIoAppend => {
+                    self.stats.file_writes += 1;
+                    let value = self.buffer.get_value(self.index);
+                    self.buffer.set_value(
+                        self.index,
+                        self.file_io.append(value)
+                    )
+                },
+                Instruction::FileSeek => {
+                    let value = self.buffer.get_value(self.index);
+                    self.buffer.set_value(
+                        self.index,
+                        self.file_io.seek(value)
+                    )
+                },
+                Instruction::ProcedureStart(x) => {
+                    let procedure = self.buffer.get_value(self.index).to_usize();
+                    self.procedures.insert(procedure, inst_ptr);
+                    inst_ptr = self.jump(inst_ptr, inst_ptr.checked_add(*x))?;
+                },
+                Instruction::ProcedureEnd => {
+                    // A procedure returns to the call
+                    if let Some(call) = self.call_stack.pop() {
+                        inst_ptr = call;
+                    }
+                },
+                Instruction::CallProcedure => {
+                    let procedure = self.buffer.get_value(self.index).to_usize();
+                    let start = match self.procedures.get(&procedure) {
+                        Some(start) => *start,
+                        None => return Err(ExecutionError::UndefinedProcedure {
+                            procedure,
+                            state: self.abort(inst_ptr),
+                        }),
+                    };
+                    if self.limits.max_depth.is_some_and(|max_depth| self.call_stack.len() >= max_depth) {
+                        return Err(ExecutionError::RecursionLimit(self.abort(inst_ptr)));
+                    }
+
+                    self.call_stack.push(inst_ptr);
+                    inst_ptr = start;
+                },
+                Instruction::BankSwitch => {
+                    // The result is written into the cell of the previous bank
+                    let previous = self.bank;
+                    let bank = self.buffer.get_value(self.index).to_usize();
+                    let result = if self.switch_bank(bank) { 0u8 } else { 1u8 };
+                    self.set_bank_value(previous, result.into());
                 }
             }
 
             inst_ptr += 1;
         }
+
+        self.inst_ptr = inst_ptr;
+        Ok(())
+    }
+
+    /// Checks that the target of a jump is an instruction of the program.
+    /// The parser only creates valid jumps, but the instructions can come
+    /// from anywhere.
+    fn jump(&mut self, inst_ptr: usize, target: Option<usize>) -> Result<usize, ExecutionError> {
+        match target {
+            Some(target) if target < self.instructions.len() => Ok(target),
+            _ => Err(ExecutionError::InvalidJump(self.abort(inst_ptr))),
+        }
+    }
+
+    fn check_memory(&mut self) -> Result<(), ExecutionError> {
+        if let Some(limit) = self.limits.max_memory {
+            self.checked_memory = self.buffer.memory_usage();
+            let required = self.checked_memory + self.banks.iter()
+                .flatten()
+                .map(|bank| bank.buffer.memory_usage())
+                .sum::<usize>();
+            if required > limit {
+                return Err(ExecutionError::MemoryLimit { required, limit });
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn dump_memory(&self) {
-        println!("{:?}", self.buffer);
+    /// Saves the instruction pointer and returns the abort state.
+    fn abort(&mut self, inst_ptr: usize) -> AbortState {
+        self.inst_ptr = inst_ptr;
+        AbortState {
+            steps: self.stats.steps,
+            index: self.index,
+            inst_ptr,
+        }
+    }
+
+    /// Dumps all banks, the active bank is marked.
+    pub fn dump_memory(&self, settings: &DumpSettings) -> Result<(), Error> {
+        dump::dump(&self.bank_views(), self.bank, settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Parser, ParserMode};
+    use crate::operations::io::test_io::TestIo;
+    use crate::operations::io::file_io::{FileIo, FileAccess};
+    use crate::buffer::{VecBuffer, PagedBuffer, DequeBuffer, TapeKind};
+
+    fn create_interpreter<'a>(code: &'a Vec<Instruction<u8>>, expected: Vec<u8>) -> Interpreter<'a, u8> {
+        let mut file_io = FileIo::with_files(Vec::new());
+        file_io.set_access(FileAccess::Disabled).unwrap();
+
+        Interpreter::new_with_io(
+            code,
+            Box::new(VecBuffer::new(16)),
+            Box::new(TestIo::new(Vec::new(), true, expected)),
+            Box::new(file_io))
+    }
+
+    #[test]
+    fn test_run() {
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("++++++++[>++++++++<-]>+.+.");
+        let mut interpreter = create_interpreter(&code, vec![b'A', b'B']);
+
+        let stats = interpreter.run().unwrap();
+        assert_eq!(stats.final_index, 1);
+        assert_eq!(stats.max_index, 1);
+        assert_eq!(stats.console_writes, 2);
+        assert_eq!(stats.console_reads, 0);
+        assert_eq!(stats.file_reads, 0);
+        assert_eq!(stats.file_writes, 0);
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("+[]");
+        let mut interpreter = create_interpreter(&code, Vec::new());
+        interpreter.set_limits(Limits {
+            max_steps: Some(100),
+            ..Limits::default()
+        });
+
+        match interpreter.run() {
+            Err(RuntimeError { error: ExecutionError::StepLimit(state), stats }) => {
+                assert_eq!(state.steps, 100);
+                assert_eq!(state.index, 0);
+                assert_eq!(stats.steps, 100);
+            },
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // The limit is not reached
+        let code = Parser::new(ParserMode::DEBUG, TapeKind::Vec, 16).parse_script("+++[-]");
+        let mut interpreter = create_interpreter(&code, Vec::new());
+        interpreter.set_limits(Limits {
+            max_steps: Some(100),
+            ..Limits::default()
+        });
+
+        assert_eq!(interpreter.run().map(|stats| stats.steps), Ok(10));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script(">+[]");
+        let mut interpreter = create_interpreter(&code, Vec::new());
+        interpreter.set_limits(Limits {
+            timeout: Some(Duration::from_millis(10)),
+            ..Limits::default()
+        });
+
+        match interpreter.run() {
+            Err(RuntimeError { error: ExecutionError::Timeout(state), .. }) => assert_eq!(state.index, 1),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("+");
+        let mut interpreter = create_interpreter(&code, Vec::new());
+        interpreter.set_limits(Limits {
+            max_memory: Some(8),
+            ..Limits::default()
+        });
+
+        let error = interpreter.run().unwrap_err();
+        assert_eq!(error.error, ExecutionError::MemoryLimit { required: 16, limit: 8 });
+        assert_eq!(error.stats.steps, 0);
+    }
+
+    #[test]
+    fn test_step() {
+        let code = Parser::new(ParserMode::DEBUG, TapeKind::Vec, 16).parse_script("++++++++[>++++++++<-]>+.+.");
+        let mut interpreter = create_interpreter(&code, vec![b'A', b'B']);
+
+        assert_eq!(interpreter.step(10), Ok(false));
+        assert_eq!(interpreter.step(0), Ok(false));
+        while !interpreter.step(7).unwrap() {}
+        assert!(interpreter.is_finished());
+        assert_eq!(interpreter.step(7), Ok(true));
+
+        // Same steps as a normal run
+        let mut other = create_interpreter(&code, vec![b'A', b'B']);
+        assert_eq!(other.run().unwrap(), interpreter.run().unwrap());
+    }
+
+    #[test]
+    fn test_pause() {
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("+[]");
+        let mut interpreter = create_interpreter(&code, Vec::new());
+
+        interpreter.pause_handle().store(true, Ordering::Relaxed);
+        match interpreter.run() {
+            Err(RuntimeError { error: ExecutionError::Paused(state), .. }) => assert_eq!(state.steps, 0),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // The next run continues
+        assert_eq!(interpreter.step(3), Ok(false));
+        interpreter.pause();
+        assert!(interpreter.step(3).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let code = Parser::new(ParserMode::DEBUG, TapeKind::Vec, 16).parse_script("++++++++[>++++++++<-]>+.+.");
+        let mut interpreter = create_interpreter(&code, vec![b'A']);
+        interpreter.set_limits(Limits {
+            max_steps: Some(108),
+            ..Limits::default()
+        });
+
+        let error = interpreter.run().unwrap_err();
+        assert!(error.error.is_resumable());
+
+        let mut data = Vec::new();
+        interpreter.snapshot().write_to(&mut data).unwrap();
+        let snapshot = Snapshot::read_from(&mut data.as_slice()).unwrap();
+
+        // Resume with the remaining output
+        let mut resumed = create_interpreter(&code, vec![b'B']);
+        resumed.restore(snapshot).unwrap();
+        let stats = resumed.run().unwrap();
+
+        let mut other = create_interpreter(&code, vec![b'A', b'B']);
+        assert_eq!(other.run().unwrap(), stats);
+
+        // Snapshots of other programs are rejected
+        let other_code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("+");
+        let mut other = create_interpreter(&other_code, Vec::new());
+        assert!(other.restore(interpreter.snapshot()).is_err());
+    }
+
+    #[test]
+    fn test_index_wrap() {
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("<+>>>");
+        let mut interpreter = create_interpreter(&code, Vec::new());
+
+        let stats = interpreter.run().unwrap();
+        assert_eq!(stats.final_index, 2);
+        assert_eq!(interpreter.buffer.get_value(15), 1);
+    }
+
+    #[test]
+    fn test_infinite_tape() {
+        let code: Vec<Instruction<u8>> = Parser::new(ParserMode::ALL, TapeKind::Infinite, 2).parse_script("+<<<+>>>>>>+");
+        let create = |code| {
+            let mut file_io = FileIo::with_files(Vec::new());
+            file_io.set_access(FileAccess::Disabled).unwrap();
+            Interpreter::new_with_io(
+                code,
+                Box::new(DequeBuffer::new(2)),
+                Box::new(TestIo::new(Vec::new(), true, Vec::new())),
+                Box::new(file_io))
+        };
+
+        let mut interpreter = create(&code);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.buffer.origin(), 3);
+        assert_eq!(interpreter.buffer.size(), 7);
+        assert_eq!(interpreter.index, 6);
+        let cells: Vec<u8> = (0..7).map(|i| interpreter.buffer.get_value(i)).collect();
+        assert_eq!(cells, vec![1, 0, 0, 1, 0, 0, 1]);
+
+        // The grown tape is restored
+        let mut restored = create(&code);
+        restored.restore(interpreter.snapshot()).unwrap();
+        assert_eq!(restored.buffer.origin(), 3);
+        assert_eq!(restored.buffer.get_value(6), 1);
+    }
+
+    #[test]
+    fn test_banks() {
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("++|>+|+++++|-|+.");
+        let mut interpreter = create_interpreter(&code, vec![1]);
+        interpreter.add_bank(Box::new(VecBuffer::new(4)));
+        interpreter.add_bank(Box::new(VecBuffer::new(4)));
+
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.bank, 0);
+        // The switch to the bank 5 fails and sets the cell to 1, `-` clears it for the switch to bank 0
+        let banks: Vec<(usize, u8)> = interpreter.bank_views().iter()
+            .map(|(buffer, index)| (*index, buffer.get_value(*index)))
+            .collect();
+        assert_eq!(banks, vec![(0, 1), (0, 0), (1, 0)]);
+
+        // The banks are part of the snapshot
+        let mut restored = create_interpreter(&code, Vec::new());
+        restored.add_bank(Box::new(VecBuffer::new(4)));
+        restored.add_bank(Box::new(VecBuffer::new(4)));
+        restored.restore(interpreter.snapshot()).unwrap();
+        assert_eq!(restored.snapshot(), interpreter.snapshot());
+
+        let mut other = create_interpreter(&code, Vec::new());
+        assert!(other.restore(interpreter.snapshot()).is_err());
+    }
+
+    #[test]
+    fn test_restore_invalid() {
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("+|>+.");
+        let mut interpreter = create_interpreter(&code, vec![1]);
+        interpreter.add_bank(Box::new(DequeBuffer::new(4)));
+        interpreter.run().unwrap();
+        let saved = interpreter.snapshot();
+
+        let mut restored = create_interpreter(&code, Vec::new());
+        restored.add_bank(Box::new(DequeBuffer::new(4)));
+        let fresh = restored.snapshot();
+
+        // The first bank is valid, nothing is restored because of the second one
+        let mut snapshot = saved.clone();
+        snapshot.banks[1].size = usize::MAX;
+        assert!(restored.restore(snapshot).is_err());
+        assert_eq!(restored.snapshot(), fresh);
+
+        let mut snapshot = saved.clone();
+        snapshot.io_state.selected = 3;
+        assert!(restored.restore(snapshot).is_err());
+        assert_eq!(restored.snapshot(), fresh);
+
+        restored.restore(saved.clone()).unwrap();
+        assert_eq!(restored.snapshot(), saved);
+    }
+
+    #[test]
+    fn test_procedures() {
+        // The procedure 0 adds 2 to the next cell and the procedure 1 calls it twice
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("(>++<)+(-%%+)%>+.");
+        let mut interpreter = create_interpreter(&code, vec![5]);
+        interpreter.set_limits(Limits {
+            max_depth: Some(2),
+            ..Limits::default()
+        });
+        interpreter.run().unwrap();
+        assert!(interpreter.call_stack.is_empty());
+
+        // The nested call exceeds the limit
+        let mut interpreter = create_interpreter(&code, Vec::new());
+        interpreter.set_limits(Limits {
+            max_depth: Some(1),
+            ..Limits::default()
+        });
+        let error = interpreter.run().unwrap_err();
+        assert!(matches!(error.error, ExecutionError::RecursionLimit(_)));
+        assert_eq!(error.exit_code(), 6);
+
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("(+)+%");
+        let mut interpreter = create_interpreter(&code, Vec::new());
+        match interpreter.run() {
+            Err(RuntimeError { error: ExecutionError::UndefinedProcedure { procedure, state }, .. }) => {
+                assert_eq!(procedure, 1);
+                assert_eq!(state.inst_ptr, 4);
+            },
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_recursion() {
+        // The procedure 1 calls itself until the next cell is 0
+        let code = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("+(>-[<%>]<)>+++<%>.");
+        let mut interpreter = create_interpreter(&code, vec![0]);
+        interpreter.set_limits(Limits {
+            max_depth: Some(3),
+            ..Limits::default()
+        });
+        interpreter.run().unwrap();
+
+        let mut interpreter = create_interpreter(&code, Vec::new());
+        interpreter.set_limits(Limits {
+            max_depth: Some(2),
+            ..Limits::default()
+        });
+        let error = interpreter.run().unwrap_err();
+        assert!(matches!(error.error, ExecutionError::RecursionLimit(_)));
+
+        // The call stack is part of the snapshot
+        let snapshot = interpreter.snapshot();
+        assert_eq!(snapshot.call_stack.len(), 2);
+        let mut resumed = create_interpreter(&code, vec![0]);
+        resumed.restore(snapshot).unwrap();
+        resumed.set_limits(Limits {
+            max_depth: Some(3),
+            ..Limits::default()
+        });
+        resumed.run().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_jump() {
+        let code = vec![Instruction::IncreaseValue(1), Instruction::LoopEnd(2)];
+        let error = create_interpreter(&code, Vec::new()).run().unwrap_err();
+        assert!(matches!(error.error, ExecutionError::InvalidJump(AbortState { inst_ptr: 1, .. })));
+        assert_eq!(error.exit_code(), 8);
+
+        let code = vec![Instruction::LoopStart(usize::MAX), Instruction::LoopEnd(1)];
+        let error = create_interpreter(&code, Vec::new()).run().unwrap_err();
+        assert!(matches!(error.error, ExecutionError::InvalidJump(_)));
+
+        let code = vec![Instruction::ProcedureStart(2), Instruction::ProcedureEnd];
+        let error = create_interpreter(&code, Vec::new()).run().unwrap_err();
+        assert!(matches!(error.error, ExecutionError::InvalidJump(_)));
+    }
+
+    #[test]
+    fn test_paged_memory_limit() {
+        let code: Vec<Instruction<u8>> = Parser::new(ParserMode::ALL, TapeKind::Paged, 1 << 40).parse_script("+[>>>>>>>>+]");
+        let mut file_io = FileIo::with_files(Vec::new());
+        file_io.set_access(FileAccess::Disabled).unwrap();
+        let mut interpreter = Interpreter::new_with_io(
+            &code,
+            Box::new(PagedBuffer::new(1 << 40)),
+            Box::new(TestIo::new(Vec::new(), true, Vec::new())),
+            Box::new(file_io));
+        interpreter.set_limits(Limits {
+            max_memory: Some(1 << 20),
+            ..Limits::default()
+        });
+
+        match interpreter.run() {
+            Err(RuntimeError { error: ExecutionError::MemoryLimit { required, limit }, stats }) => {
+                assert!(required > limit);
+                assert!(stats.max_index > 1 << 10);
+            },
+            result => panic!("Unexpected result: {:?}", result),
+        }
     }
 }
@@ -0,0 +1,245 @@
+//    +,-
+//   {^.°}
+//   <[@]>
+//    : ;
+//
+// Meet BDB the BrainDamageBot he is here to make sure that
+// all code is awesome and readable
+
+mod settings;
+pub use settings::Settings;
+
+mod parser;
+mod operations;
+pub use operations::Instruction;
+
+mod buffer;
+mod dump;
+mod cell;
+pub use cell::Cell;
+pub mod interpreter;
+use interpreter::{ExecutionError, Interpreter, RunStats, RuntimeError, Snapshot};
+mod snapshot;
+mod signal;
+mod tools;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzzing;
+#[cfg(test)]
+mod differential;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::process;
+use crate::settings::{CellType, Command, InputSource, OutputTarget};
+use crate::parser::{find_deep_block, printer, EmitFormat, MAX_NESTING, Parser, ParserMode, Pass, Preprocessor, MacroProcessor};
+use crate::operations::io::BraindamageIo;
+use crate::operations::io::console_io::ConsoleIo;
+use crate::operations::io::stream_io::StreamIo;
+use crate::operations::io::file_io::FileIo;
+
+/// Runs the command line interface with the arguments, including the
+/// program name. Returns the exit code.
+pub fn run<T>(args: T) -> i32
+    where T: Iterator<Item=String>
+{
+    let settings = Settings::parse_args(args);
+
+    if settings.print_passes {
+        print_passes(settings.parser_mode);
+        return 0;
+    }
+
+    if settings.command != Command::Run {
+        return run_command(&settings);
+    }
+
+    match settings.cell_type {
+        CellType::U8 => run_with_t::<u8>(settings),
+        CellType::U16 => run_with_t::<u16>(settings),
+        CellType::U32 => run_with_t::<u32>(settings),
+    }
+}
+
+fn print_passes(mode: ParserMode) {
+    println!("{:<16} {:<6} {:<8} Description", "Pass", "Level", "Enabled");
+    for pass in Pass::ALL {
+        let enabled = if mode.is_enabled(pass) { "yes" } else { "no" };
+        println!("{:<16} -O{:<4} {:<8} {}", pass.name(), pass.level(), enabled, pass.description());
+    }
+}
+
+fn run_command(settings: &Settings) -> i32 {
+    let file = match &settings.tool_file {
+        Some(file) => file,
+        None => {
+            println!("Please enter a file for the command.");
+            return -1;
+        }
+    };
+
+    match settings.command {
+        Command::Fmt => tools::fmt::run(file, settings.check, settings.width),
+        Command::Minify => tools::minify::run(file, &settings.lib_paths, settings.macros),
+        Command::Lint => {
+            let wrap = settings.tape_kind.wrap_size(settings.buffer_size);
+            tools::lint::run(file, &settings.lib_paths, settings.macros, wrap)
+        },
+        Command::Run => 0,
+    }
+}
+
+fn run_with_t<T>(settings: Settings) -> i32
+    where T: Cell
+{
+    let preprocessor = Preprocessor::new(settings.lib_paths.clone());
+    let mut source = match preprocessor.process(&settings.get_src(), settings.src_file.as_deref()) {
+        Ok(source) => source,
+        Err(error) => {
+            println!("Unable to resolve the includes: {}", error);
+            return -1;
+        }
+    };
+    if settings.macros {
+        source = match MacroProcessor::new().expand(source) {
+            Ok(source) => source,
+            Err(error) => {
+                println!("Unable to expand the macros: {}", error);
+                return -1;
+            }
+        };
+    }
+
+    if let Some(start) = find_deep_block(&source.code, MAX_NESTING) {
+        println!("{}: The block is nested deeper than {} levels", source.location(start), MAX_NESTING);
+        return -1;
+    }
+
+    let mut parser = Parser::new(settings.parser_mode, settings.tape_kind, settings.buffer_size);
+    if let Some(pass) = settings.dump_after {
+        if !settings.parser_mode.is_enabled(pass) {
+            eprintln!("The pass {} is not enabled, there is nothing to dump.", pass);
+        }
+        parser.set_dump_after(pass, settings.emit.unwrap_or(EmitFormat::Tokens));
+    }
+    let tokens = parser.parse_tokens(&source.code);
+    if let Some(format) = settings.emit {
+        print!("{}", printer::emit(&tokens, format));
+        return 0;
+    }
+    let code: Vec<Instruction<T>> = parser.flatten(tokens);
+
+    let mut file_io = FileIo::with_files(settings.get_data_files());
+    file_io.set_verbose(settings.verbose);
+    if let Err(error) = file_io.set_access(settings.file_access.clone()) {
+        println!("Unable to use the sandbox directory: {}", error);
+        process::exit(-1);
+    }
+
+    // The tapes are allocated before the interpreter checks the limit
+    let required = settings.tape_kind.initial_memory::<T>(settings.buffer_size).saturating_mul(settings.banks);
+    if let Some(limit) = settings.limits.max_memory.filter(|limit| required > *limit) {
+        let error = RuntimeError {
+            error: ExecutionError::MemoryLimit { required, limit },
+            stats: RunStats::default(),
+        };
+        eprintln!("Error: {}", error);
+        return error.exit_code();
+    }
+
+    let mut thing = Interpreter::new_with_io(
+        &code,
+        settings.tape_kind.create(settings.buffer_size),
+        create_console_io(&settings),
+        Box::new(file_io));
+    thing.set_limits(settings.limits);
+    // Without a snapshot a signal ends the program like usual
+    if settings.snapshot_file.is_some() {
+        if let Err(error) = signal::pause_on_signal(thing.pause_handle()) {
+            eprintln!("Unable to handle SIGINT and SIGTERM: {}", error);
+        }
+    }
+    for _ in 1..settings.banks {
+        thing.add_bank(settings.tape_kind.create(settings.buffer_size));
+    }
+
+    if let Some(file_name) = &settings.resume_file {
+        if let Err(error) = Snapshot::load(file_name).and_then(|snapshot| thing.restore(snapshot)) {
+            println!("Unable to resume from the snapshot \"{}\": {}", file_name, error);
+            return -1;
+        }
+    }
+
+    let result = thing.run();
+
+    if settings.dump_mem {
+        if let Err(error) = thing.dump_memory(&settings.dump) {
+            eprintln!("Unable to dump the memory: {}", error);
+        }
+    }
+
+    let exit_code = match result {
+        Ok(stats) => {
+            if settings.print_stats {
+                eprintln!("{}", stats);
+            }
+            0
+        },
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            if settings.print_stats {
+                eprintln!("{}", error.stats);
+            }
+
+            if let Some(file_name) = &settings.snapshot_file {
+                if error.error.is_resumable() {
+                    match thing.snapshot().save(file_name) {
+                        Ok(_) => eprintln!("The state was saved to \"{}\"", file_name),
+                        Err(error) => eprintln!("Unable to save the snapshot \"{}\": {}", file_name, error),
+                    }
+                }
+            }
+
+            error.exit_code()
+        }
+    };
+
+    // A full disk or a closed pipe would otherwise truncate the output silently
+    if let Err(error) = thing.flush_output() {
+        eprintln!("Unable to write the output: {}", error);
+        if exit_code == 0 {
+            return -1;
+        }
+    }
+
+    exit_code
+}
+
+fn create_console_io<T>(settings: &Settings) -> Box<dyn BraindamageIo<T>>
+    where T: Cell
+{
+    let writer: Box<dyn Write> = match &settings.output {
+        OutputTarget::Console => Box::new(io::stdout()),
+        OutputTarget::File(file_name) => match File::create(file_name) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(_) => {
+                println!("Unable to create the output file: \"{}\"", file_name);
+                process::exit(-1);
+            }
+        },
+    };
+
+    let reader: Box<dyn Read> = match &settings.input {
+        // The console is read line by line
+        InputSource::Console => return Box::new(ConsoleIo::new(writer)),
+        InputSource::File(file_name) => match File::open(file_name) {
+            Ok(file) => Box::new(file),
+            Err(_) => {
+                println!("Unable to open the input file: \"{}\"", file_name);
+                process::exit(-1);
+            }
+        },
+        InputSource::Str(text) => Box::new(io::Cursor::new(text.clone().into_bytes())),
+    };
+
+    Box::new(StreamIo::new(reader, writer))
+}
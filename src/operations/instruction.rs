@@ -1,4 +1,5 @@
 use crate::Cell;
+use std::cmp::Ordering;
 
 #[repr(u8)]
 #[derive(Debug, PartialEq, Clone)]
@@ -15,6 +16,17 @@ pub enum Instruction<T: Cell> {
 
     FileIoRead,
     FileIoWrite,
+    FileSelect,
+    FileIoAppend,
+    FileSeek,
+
+    BankSwitch,
+
+    /// Defines the procedure with the number at the data pointer and skips its body.
+    ProcedureStart(usize),
+    /// Returns from the procedure.
+    ProcedureEnd,
+    CallProcedure,
 }
 
 impl<T> Instruction<T>
@@ -23,25 +35,64 @@ impl<T> Instruction<T>
 {
 
     pub fn can_join(&self, other: &Instruction<T>) -> bool {
-        match (self, other) {
+        matches!((self, other),
             (Instruction::IncreaseIndex(_), Instruction::IncreaseIndex(_)) |
             (Instruction::DecreaseIndex(_), Instruction::DecreaseIndex(_)) |
             (Instruction::IncreaseValue(_), Instruction::IncreaseValue(_)) |
-            (Instruction::DecreaseValue(_), Instruction::DecreaseValue(_)) => {
-                true
-            },
-            _ => false
-        }
+            (Instruction::DecreaseValue(_), Instruction::DecreaseValue(_)))
     }
 
     pub fn join(self, other: Instruction<T>) -> Instruction<T> {
         match (self, other) {
             (Instruction::IncreaseIndex(x), Instruction::IncreaseIndex(y)) => Instruction::IncreaseIndex(x + y),
             (Instruction::DecreaseIndex(x), Instruction::DecreaseIndex(y)) => Instruction::DecreaseIndex(x + y),
-            (Instruction::IncreaseValue(x)   , Instruction::IncreaseValue(y  )) => Instruction::IncreaseValue(x + y),
-            (Instruction::DecreaseValue(x)   , Instruction::DecreaseValue(y  )) => Instruction::DecreaseValue(x + y),
+            (Instruction::IncreaseValue(x)   , Instruction::IncreaseValue(y  )) => Instruction::IncreaseValue(x.add_overflow(&y)),
+            (Instruction::DecreaseValue(x)   , Instruction::DecreaseValue(y  )) => Instruction::DecreaseValue(x.add_overflow(&y)),
             (this, _) => this
         }
     }
+
+    /// Returns `true` if the instructions undo each other, like `+` and `-`.
+    pub fn is_opposite(&self, other: &Instruction<T>) -> bool {
+        matches!((self, other),
+            (Instruction::IncreaseIndex(_), Instruction::DecreaseIndex(_)) |
+            (Instruction::DecreaseIndex(_), Instruction::IncreaseIndex(_)) |
+            (Instruction::IncreaseValue(_), Instruction::DecreaseValue(_)) |
+            (Instruction::DecreaseValue(_), Instruction::IncreaseValue(_)))
+    }
+
+    /// Combines two opposite instructions. Returns `None` if they cancel out.
+    pub fn cancel(self, other: Instruction<T>) -> Option<Instruction<T>> {
+        let (inc, dec) = match (self, other) {
+            (Instruction::IncreaseIndex(x), Instruction::DecreaseIndex(y)) => (x, y),
+            (Instruction::DecreaseIndex(x), Instruction::IncreaseIndex(y)) => (y, x),
+            (Instruction::IncreaseValue(x), Instruction::DecreaseValue(y)) => {
+                return Self::cancel_value(x, y, Instruction::IncreaseValue, Instruction::DecreaseValue)
+            },
+            (Instruction::DecreaseValue(x), Instruction::IncreaseValue(y)) => {
+                return Self::cancel_value(x, y, Instruction::DecreaseValue, Instruction::IncreaseValue)
+            },
+            (this, _) => return Some(this),
+        };
+
+        match inc.cmp(&dec) {
+            Ordering::Equal => None,
+            Ordering::Greater => Some(Instruction::IncreaseIndex(inc - dec)),
+            Ordering::Less => Some(Instruction::DecreaseIndex(dec - inc)),
+        }
+    }
+
+    fn cancel_value(
+        x: T,
+        y: T,
+        first: fn(T) -> Instruction<T>,
+        second: fn(T) -> Instruction<T>) -> Option<Instruction<T>>
+    {
+        match x.to_usize().cmp(&y.to_usize()) {
+            Ordering::Equal => None,
+            Ordering::Greater => Some(first(x.sub_overflow(&y))),
+            Ordering::Less => Some(second(y.sub_overflow(&x))),
+        }
+    }
 }
 
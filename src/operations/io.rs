@@ -1,7 +1,9 @@
 use crate::Cell;
+use std::io::Error;
 
 pub mod file_io;
 pub mod console_io;
+pub mod stream_io;
 pub mod test_io;
 
 const BUFFER_END_VALUE : u8 = 0;
@@ -12,4 +14,40 @@ pub trait BraindamageIo<T: Cell> {
     fn read(&mut self) -> T;
 
     fn write(&mut self, value: T) -> T;
+
+    /// Writes the buffered output. Returns the first error of the writes
+    /// since the last flush, the program itself can't see them.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub trait BraindamageFileIo<T: Cell>: BraindamageIo<T> {
+    /// Selects the file that is used for the following reads and writes.
+    fn select(&mut self, handle: T) -> T;
+
+    /// Works like `write`, but the buffer is appended to the file once it is saved.
+    fn append(&mut self, value: T) -> T;
+
+    /// Moves the read position of the selected file to the given cell offset.
+    fn seek(&mut self, offset: T) -> T;
+
+    fn save_state(&self) -> FileIoState<T>;
+
+    /// Restores a state from `save_state`. The file table has to have the same size.
+    fn restore_state(&mut self, state: FileIoState<T>) -> Result<(), Error>;
+}
+
+/// The buffers of a file handle, used for snapshots.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileHandleState<T: Cell> {
+    pub r_buffer: Vec<T>,
+    pub r_index: usize,
+    pub w_buffer: Vec<T>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileIoState<T: Cell> {
+    pub selected: usize,
+    pub handles: Vec<FileHandleState<T>>,
 }
\ No newline at end of file
@@ -1,13 +1,22 @@
 use crate::operations::io::BraindamageIo;
 use crate::Cell;
-use std::io;
+use std::io::{self, Error, Write};
 
-pub struct ConsoleIo;
+/// Reads the input line by line from the console, only the first byte of a
+/// line is used. The output goes to the writer, usually stdout.
+pub struct ConsoleIo {
+    writer: Box<dyn Write>,
+    /// The first write error, it's reported by `flush`.
+    error: Option<Error>,
+}
 
 impl<T> BraindamageIo<T> for ConsoleIo
     where T: Cell
 {
     fn read(&mut self) -> T {
+        // The prompt of the program is shown before the input is read
+        let _result = self.writer.flush();
+
         let mut buffer = String::new();
         let _result = io::stdin().read_line(&mut buffer);
         buffer.bytes().next().unwrap_or_default().into()
@@ -15,15 +24,27 @@ impl<T> BraindamageIo<T> for ConsoleIo
 
     fn write(&mut self, value: T) -> T {
         let c = value.to_char();
-        print!("{}", c);
+        if let Err(error) = write!(self.writer, "{}", c) {
+            self.error.get_or_insert(error);
+        }
         value
     }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        self.writer.flush()
+    }
 }
 
 impl ConsoleIo {
-    pub fn new() -> Self {
+    /// Reads from the console and writes the output to the writer.
+    pub fn new(writer: Box<dyn Write>) -> Self {
         ConsoleIo {
-
+            writer,
+            error: None,
         }
     }
-}
\ No newline at end of file
+}
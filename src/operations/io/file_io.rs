@@ -1,12 +1,13 @@
-use std::fs::File;
-use std::io::{Write, Read};
+use std::fs::{File, OpenOptions};
+use std::io::{Write, Read, Error, ErrorKind};
+use std::path::{Path, PathBuf, Component};
 use std::{slice, mem};
 use crate::operations::io;
-use crate::operations::io::BraindamageIo;
+use crate::operations::io::{BraindamageIo, BraindamageFileIo, FileIoState, FileHandleState};
 use crate::Cell;
 
-pub struct FileIo<'a, T: Cell> {
-    file: &'a str,
+struct FileHandle<T: Cell> {
+    file: String,
 
     r_buffer: Vec<T>,
     r_index: usize,
@@ -14,11 +15,11 @@ pub struct FileIo<'a, T: Cell> {
     w_buffer: Vec<T>,
 }
 
-impl<'a, T> FileIo<'a, T>
+impl<T> FileHandle<T>
     where T: Cell
 {
-    pub(crate) fn new(file_name: &'a str) -> Self {
-        FileIo {
+    fn new(file_name: String) -> Self {
+        FileHandle {
             file: file_name,
 
             r_buffer: Vec::new(),
@@ -28,82 +29,283 @@ impl<'a, T> FileIo<'a, T>
         }
     }
 
-    fn read_file(&mut self) {
+    fn read_file(&mut self, access: &FileAccess) -> Result<(), Error> {
         self.r_buffer.clear();
         self.r_index = 0;
 
-        let file = File::open(self.file);
-        if file.is_ok() {
-            let mut file = file.unwrap();
-            let mut buffer = Vec::new();
+        let path = access.resolve(&self.file)?;
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            // A missing file is read like an empty file
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
 
-            // read the whole file
-            file.read_to_end(&mut buffer).unwrap();
+        // read the whole file
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
 
-            let cell_data = &buffer[..];
-            let read_cells = unsafe {
-                let ptr = cell_data.as_ptr() as *const T;
-                slice::from_raw_parts(ptr, cell_data.len() / mem::size_of::<T>())
-            };
+        let cell_data = &buffer[..];
+        let read_cells = unsafe {
+            let ptr = cell_data.as_ptr() as *const T;
+            slice::from_raw_parts(ptr, cell_data.len() / mem::size_of::<T>())
+        };
 
-            self.r_buffer.append(&mut read_cells.to_vec());
-        } else {
-            // TODO Add debug info.
-        }
+        self.r_buffer.append(&mut read_cells.to_vec());
+        Ok(())
     }
 
-    fn write_file(&mut self) -> T {
-        let mut file = File::create(self.file).unwrap();
-
+    fn write_file(&mut self, access: &FileAccess, append: bool) -> Result<T, Error> {
         let shared_data = &self.w_buffer[..];
         let bytes = unsafe {
-            let len = shared_data.len() * mem::size_of::<T>();
+            let len = mem::size_of_val(shared_data);
             let ptr = shared_data.as_ptr() as *const u8;
             slice::from_raw_parts(ptr, len)
         };
-        let res = file.write_all(bytes);
+
+        let res = access.resolve(&self.file).and_then(|path| OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(!append)
+            .append(append)
+            .open(path))
+            .and_then(|mut file| file.write_all(bytes));
 
         self.w_buffer.clear();
-        let result : u8 = if res.is_ok() { io::RET_IO_OK } else { io::RET_IO_ERR };
-        return result.into();
+        res.map(|_| io::RET_IO_OK.into())
     }
-}
 
-impl<'a, T> BraindamageIo<T> for FileIo<'a, T>
-    where T: Cell
-{
-    fn read(&mut self) -> T {
+    fn read(&mut self, access: &FileAccess) -> Result<T, Error> {
         if self.r_index == 0 {
-            self.read_file();
+            self.read_file(access)?;
         }
 
         if self.r_index >= self.r_buffer.len() {
             self.r_index = 0;
-            return io::BUFFER_END_VALUE.clone().into();
+            return Ok(io::BUFFER_END_VALUE.into());
         }
 
-        let value = self.r_buffer[self.r_index].clone();
+        let value = self.r_buffer[self.r_index];
         self.r_index += 1;
-        value
+        Ok(value)
     }
 
-    fn write(&mut self, value: T) -> T {
+    fn write(&mut self, access: &FileAccess, value: T, append: bool) -> Result<T, Error> {
         if value == io::BUFFER_END_VALUE.into() {
-            return self.write_file();
+            return self.write_file(access, append);
+        }
+
+        self.w_buffer.push(value);
+        Ok(value)
+    }
+
+    fn seek(&mut self, access: &FileAccess, offset: usize) -> Result<T, Error> {
+        self.read_file(access)?;
+
+        if offset > self.r_buffer.len() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("the offset {} is behind the end of the file", offset)));
         }
 
-        self.w_buffer.push(value.clone());
-        value
+        self.r_index = offset;
+        Ok(io::RET_IO_OK.into())
+    }
+}
+
+/// Restricts the files that can be accessed by the file IO.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FileAccess {
+    Unrestricted,
+    /// All paths are resolved inside this directory. Paths that leave the
+    /// directory with `..` or symlinks are rejected.
+    Sandbox(PathBuf),
+    Disabled,
+}
+
+impl FileAccess {
+    fn resolve(&self, file: &str) -> Result<PathBuf, Error> {
+        match self {
+            FileAccess::Unrestricted => Ok(PathBuf::from(file)),
+            FileAccess::Sandbox(dir) => sandbox_path(dir, file),
+            FileAccess::Disabled => Err(Error::new(ErrorKind::PermissionDenied, "file IO is disabled")),
+        }
+    }
+}
+
+/// Resolves the file inside the sandbox directory. The directory has to be
+/// canonicalized.
+fn sandbox_path(dir: &Path, file: &str) -> Result<PathBuf, Error> {
+    let path = Path::new(file);
+    if path.components().any(|component| component == Component::ParentDir) {
+        return Err(Error::new(ErrorKind::PermissionDenied, "`..` is not allowed in the sandbox"));
+    }
+
+    // Relative paths are relative to the sandbox
+    let path = dir.join(path);
+
+    // The deepest existing ancestor is resolved to detect symlinks that leave
+    // the sandbox. The remaining components can't be links as they don't exist.
+    let mut existing = path.as_path();
+    while existing.symlink_metadata().is_err() {
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+
+    if !existing.canonicalize()?.starts_with(dir) {
+        return Err(Error::new(ErrorKind::PermissionDenied, "the path leaves the sandbox"));
+    }
+
+    Ok(path)
+}
+
+/// The file IO holds a table of files. The file that is used by `:` and `;`
+/// is selected with `#`. Every file keeps its own read and write buffer.
+///
+/// IO errors are returned to the program as `RET_IO_ERR`. They are also
+/// printed to stderr if the IO is verbose.
+pub struct FileIo<T: Cell> {
+    handles: Vec<FileHandle<T>>,
+    selected: usize,
+    verbose: bool,
+    access: FileAccess,
+}
+
+impl<T> FileIo<T>
+    where T: Cell
+{
+    #[cfg(test)]
+    pub(crate) fn new(file_name: &str) -> Self {
+        Self::with_files(vec![file_name.to_string()])
+    }
+
+    pub(crate) fn with_files(file_names: Vec<String>) -> Self {
+        FileIo {
+            handles: file_names.into_iter().map(FileHandle::new).collect(),
+            selected: 0,
+            verbose: false,
+            access: FileAccess::Unrestricted,
+        }
+    }
+
+    pub(crate) fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    /// Sets the access policy. A sandbox directory has to exist.
+    pub(crate) fn set_access(&mut self, access: FileAccess) -> Result<(), Error> {
+        self.access = match access {
+            FileAccess::Sandbox(dir) => FileAccess::Sandbox(dir.canonicalize()?),
+            access => access,
+        };
+
+        Ok(())
+    }
+
+    fn run<F>(&mut self, action: &str, operation: F) -> T
+        where F: FnOnce(&mut FileHandle<T>, &FileAccess) -> Result<T, Error>
+    {
+        let handle = match self.handles.get_mut(self.selected) {
+            Some(handle) => handle,
+            None => {
+                self.warn(format_args!("Unable to {}: No file is selected", action));
+                return io::RET_IO_ERR.into();
+            }
+        };
+
+        match operation(handle, &self.access) {
+            Ok(value) => value,
+            Err(error) => {
+                let file = handle.file.clone();
+                self.warn(format_args!("Unable to {} the file \"{}\": {}", action, file, error));
+                io::RET_IO_ERR.into()
+            }
+        }
+    }
+
+    fn warn(&self, message: std::fmt::Arguments) {
+        if self.verbose {
+            eprintln!("Warning: {}", message);
+        }
+    }
+}
+
+impl<T> BraindamageIo<T> for FileIo<T>
+    where T: Cell
+{
+    fn read(&mut self) -> T {
+        self.run("read", |handle, access| handle.read(access))
+    }
+
+    fn write(&mut self, value: T) -> T {
+        self.run("write", |handle, access| handle.write(access, value, false))
+    }
+}
+
+impl<T> BraindamageFileIo<T> for FileIo<T>
+    where T: Cell
+{
+    fn select(&mut self, handle: T) -> T {
+        let handle = handle.to_usize();
+        if handle >= self.handles.len() {
+            self.warn(format_args!("Unable to select the file handle {}: The file table has {} entries",
+                handle, self.handles.len()));
+            return io::RET_IO_ERR.into();
+        }
+
+        self.selected = handle;
+        io::RET_IO_OK.into()
+    }
+
+    fn append(&mut self, value: T) -> T {
+        self.run("append", |handle, access| handle.write(access, value, true))
+    }
+
+    fn seek(&mut self, offset: T) -> T {
+        self.run("seek", |handle, access| handle.seek(access, offset.to_usize()))
+    }
+
+    fn save_state(&self) -> FileIoState<T> {
+        FileIoState {
+            selected: self.selected,
+            handles: self.handles.iter()
+                .map(|handle| FileHandleState {
+                    r_buffer: handle.r_buffer.clone(),
+                    r_index: handle.r_index,
+                    w_buffer: handle.w_buffer.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn restore_state(&mut self, state: FileIoState<T>) -> Result<(), Error> {
+        if state.handles.len() != self.handles.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("the state has {} files but the file table has {} entries",
+                    state.handles.len(), self.handles.len())));
+        }
+
+        self.selected = state.selected;
+        for (handle, state) in self.handles.iter_mut().zip(state.handles) {
+            handle.r_buffer = state.r_buffer;
+            handle.r_index = state.r_index;
+            handle.w_buffer = state.w_buffer;
+        }
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::remove_file;
+    use std::fs::{remove_file, create_dir_all, remove_dir_all};
 
     fn delete_file(file: &str) {
-        remove_file(file);
+        let _ = remove_file(file);
     }
 
     #[test]
@@ -126,7 +328,7 @@ mod tests {
 
     #[test]
     fn test_read_write() {
-        const TEST_FILE : &'static str = "fileio_test_read_write.test";
+        const TEST_FILE : &str = "fileio_test_read_write.test";
 
         delete_file(TEST_FILE);
         test_read_write_type(TEST_FILE, &[1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8]);
@@ -150,27 +352,27 @@ mod tests {
 
         for var in data {
             // No file -> read 0
-            assert_eq!(io.write(var.clone()), var.clone());
+            assert_eq!(io.write(*var), *var);
             assert_eq!(io.read(), 0u8.into());
         }
 
         assert_eq!(io.write(0u8.into()), 0u8.into());
         for var in data {
-            assert_eq!(io.read(), var.clone());
+            assert_eq!(io.read(), *var);
         }
         assert_eq!(io.read(), 0u8.into());
         for var in data {
-            assert_eq!(io.read(), var.clone());
+            assert_eq!(io.read(), *var);
         }
     }
 
     #[test]
     fn test_only_load_on_zero() {
-        const TEST_FILE : &'static str = "fileio_only_load_on_zero.test";
+        const TEST_FILE : &str = "fileio_only_load_on_zero.test";
 
         delete_file(TEST_FILE);
 
-        let mut io : FileIo<u8> = FileIo::new(&TEST_FILE);
+        let mut io : FileIo<u8> = FileIo::new(TEST_FILE);
 
         // Write
         assert_eq!(io.read(), 0u8);
@@ -220,11 +422,11 @@ mod tests {
 
     #[test]
     fn test_clear_file_on_small_buffer() {
-        const TEST_FILE : &'static str = "fileio_test_clear_file_on_small_buffer.test";
+        const TEST_FILE : &str = "fileio_test_clear_file_on_small_buffer.test";
 
         delete_file(TEST_FILE);
 
-        let mut io : FileIo<u8> = FileIo::new(&TEST_FILE);
+        let mut io : FileIo<u8> = FileIo::new(TEST_FILE);
 
         // Write 111
         assert_eq!(io.read(), 0u8);
@@ -248,4 +450,263 @@ mod tests {
         delete_file(TEST_FILE);
     }
 
+    #[test]
+    fn test_select_file() {
+        const TEST_FILE_A : &str = "fileio_test_select_file_a.test";
+        const TEST_FILE_B : &str = "fileio_test_select_file_b.test";
+
+        delete_file(TEST_FILE_A);
+        delete_file(TEST_FILE_B);
+
+        let mut io : FileIo<u8> = FileIo::with_files(
+            vec![TEST_FILE_A.to_string(), TEST_FILE_B.to_string()]);
+
+        // Write A
+        assert_eq!(io.write(b'A'), b'A');
+        assert_eq!(io.select(1u8), 0u8);
+        // Write B, the write buffer of A is kept
+        assert_eq!(io.write(b'B'), b'B');
+        assert_eq!(io.write(0u8), 0u8); // Save
+        assert_eq!(io.select(0u8), 0u8);
+        assert_eq!(io.write(0u8), 0u8); // Save
+
+        // Invalid handles keep the selection
+        assert_eq!(io.select(2u8), 1u8);
+
+        assert_eq!(io.read(), b'A');
+        assert_eq!(io.read(), 0u8); // Read End of file
+        assert_eq!(io.select(1u8), 0u8);
+        assert_eq!(io.read(), b'B');
+        assert_eq!(io.read(), 0u8); // Read End of file
+
+        delete_file(TEST_FILE_A);
+        delete_file(TEST_FILE_B);
+    }
+
+    #[test]
+    fn test_append() {
+        const TEST_FILE : &str = "fileio_test_append.test";
+
+        delete_file(TEST_FILE);
+
+        let mut io : FileIo<u8> = FileIo::new(TEST_FILE);
+
+        // Append creates the file
+        assert_eq!(io.append(1u8), 1u8);
+        assert_eq!(io.append(0u8), 0u8); // Save
+        assert_eq!(io.append(2u8), 2u8);
+        assert_eq!(io.append(0u8), 0u8); // Save
+
+        assert_eq!(io.read(), 1u8);
+        assert_eq!(io.read(), 2u8);
+        assert_eq!(io.read(), 0u8); // Read End of file
+
+        // A normal write still truncates
+        assert_eq!(io.write(3u8), 3u8);
+        assert_eq!(io.write(0u8), 0u8); // Save
+
+        assert_eq!(io.read(), 3u8);
+        assert_eq!(io.read(), 0u8); // Read End of file
+
+        delete_file(TEST_FILE);
+    }
+
+    #[test]
+    fn test_seek() {
+        const TEST_FILE : &str = "fileio_test_seek.test";
+
+        delete_file(TEST_FILE);
+
+        let mut io : FileIo<u16> = FileIo::new(TEST_FILE);
+
+        assert_eq!(io.write(1u16), 1u16);
+        assert_eq!(io.write(2u16), 2u16);
+        assert_eq!(io.write(3u16), 3u16);
+        assert_eq!(io.write(0u16), 0u16); // Save
+
+        assert_eq!(io.seek(2u16), 0u16);
+        assert_eq!(io.read(), 3u16);
+        assert_eq!(io.read(), 0u16); // Read End of file
+
+        assert_eq!(io.read(), 1u16);
+        assert_eq!(io.seek(1u16), 0u16);
+        assert_eq!(io.read(), 2u16);
+
+        // Seeking to the end reads the end of file
+        assert_eq!(io.seek(3u16), 0u16);
+        assert_eq!(io.read(), 0u16);
+
+        // Seeking behind the end fails
+        assert_eq!(io.seek(4u16), 1u16);
+        assert_eq!(io.read(), 1u16);
+
+        delete_file(TEST_FILE);
+    }
+
+    #[test]
+    fn test_write_error() {
+        const TEST_FILE : &str = "fileio_test_write_error_missing_dir/file.test";
+
+        let mut io : FileIo<u8> = FileIo::new(TEST_FILE);
+        io.set_verbose(true);
+
+        assert_eq!(io.write(1u8), 1u8);
+        assert_eq!(io.write(0u8), 1u8); // Save fails
+        assert_eq!(io.append(1u8), 1u8);
+        assert_eq!(io.append(0u8), 1u8); // Save fails
+
+        // Directories can't be written
+        let mut io : FileIo<u8> = FileIo::new(".");
+
+        assert_eq!(io.write(1u8), 1u8);
+        assert_eq!(io.write(0u8), 1u8); // Save fails
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_error_permissions() {
+        use std::fs::{set_permissions, write, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        const DIR : &str = "fileio_test_write_error_read_only";
+        let file = format!("{}/file.test", DIR);
+
+        let _ = remove_dir_all(DIR);
+        create_dir_all(DIR).unwrap();
+        set_permissions(DIR, Permissions::from_mode(0o555)).unwrap();
+
+        // Root ignores the permissions, the test is skipped
+        if write(&file, b"").is_err() {
+            let mut io : FileIo<u8> = FileIo::new(&file);
+            io.set_verbose(true);
+
+            assert_eq!(io.write(1u8), 1u8);
+            assert_eq!(io.write(0u8), 1u8); // Save fails
+            assert_eq!(io.append(1u8), 1u8);
+            assert_eq!(io.append(0u8), 1u8); // Save fails
+        }
+
+        set_permissions(DIR, Permissions::from_mode(0o755)).unwrap();
+        remove_dir_all(DIR).unwrap();
+    }
+
+    #[test]
+    fn test_read_error() {
+        // Directories can be opened but not read
+        let mut io : FileIo<u8> = FileIo::new(".");
+
+        assert_eq!(io.read(), 1u8);
+        assert_eq!(io.read(), 1u8);
+        assert_eq!(io.seek(0u8), 1u8);
+
+        // Missing files are read as empty files
+        let mut io : FileIo<u8> = FileIo::new("fileio_test_read_error_missing_dir/file.test");
+
+        assert_eq!(io.read(), 0u8);
+        assert_eq!(io.seek(0u8), 0u8);
+        assert_eq!(io.seek(1u8), 1u8);
+    }
+
+    #[test]
+    fn test_no_files() {
+        let mut io : FileIo<u8> = FileIo::with_files(Vec::new());
+
+        assert_eq!(io.read(), 1u8);
+        assert_eq!(io.write(5u8), 1u8);
+        assert_eq!(io.select(0u8), 1u8);
+    }
+
+    #[test]
+    fn test_sandbox() {
+        const SANDBOX : &str = "fileio_test_sandbox";
+
+        let _ = remove_dir_all(SANDBOX);
+        create_dir_all(format!("{}/dir", SANDBOX)).unwrap();
+
+        let mut io : FileIo<u8> = FileIo::with_files(vec![
+            "file.test".to_string(),
+            "dir/file.test".to_string(),
+            "../file.test".to_string(),
+            "dir/../file.test".to_string(),
+            "/tmp/fileio_test_sandbox.test".to_string(),
+            "missing/file.test".to_string(),
+        ]);
+        assert!(io.set_access(FileAccess::Sandbox(PathBuf::from(SANDBOX))).is_ok());
+
+        // Paths in the sandbox
+        assert_eq!(io.write(1u8), 1u8);
+        assert_eq!(io.write(0u8), 0u8); // Save
+        assert!(Path::new(SANDBOX).join("file.test").exists());
+        assert_eq!(io.select(1u8), 0u8);
+        assert_eq!(io.write(1u8), 1u8);
+        assert_eq!(io.write(0u8), 0u8); // Save
+        assert!(Path::new(SANDBOX).join("dir/file.test").exists());
+
+        // Paths that leave the sandbox
+        for handle in 2u8..5u8 {
+            assert_eq!(io.select(handle), 0u8);
+            assert_eq!(io.write(1u8), 1u8);
+            assert_eq!(io.write(0u8), 1u8); // Save fails
+            assert_eq!(io.read(), 1u8);
+        }
+        assert!(!Path::new("file.test").exists());
+
+        // Missing directories fail like normal
+        assert_eq!(io.select(5u8), 0u8);
+        assert_eq!(io.read(), 0u8);
+        assert_eq!(io.write(1u8), 1u8);
+        assert_eq!(io.write(0u8), 1u8); // Save fails
+
+        let _ = remove_dir_all(SANDBOX);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sandbox_symlink() {
+        use std::os::unix::fs::symlink;
+        const SANDBOX : &str = "fileio_test_sandbox_symlink";
+        const OUTSIDE : &str = "fileio_test_sandbox_symlink_outside";
+
+        let _ = remove_dir_all(SANDBOX);
+        let _ = remove_dir_all(OUTSIDE);
+        create_dir_all(SANDBOX).unwrap();
+        create_dir_all(OUTSIDE).unwrap();
+        let outside = Path::new(OUTSIDE).canonicalize().unwrap();
+        symlink(&outside, Path::new(SANDBOX).join("dir_link")).unwrap();
+        symlink(outside.join("file.test"), Path::new(SANDBOX).join("file_link")).unwrap();
+
+        let mut io : FileIo<u8> = FileIo::with_files(vec![
+            "dir_link/file.test".to_string(),
+            "file_link".to_string(),
+        ]);
+        assert!(io.set_access(FileAccess::Sandbox(PathBuf::from(SANDBOX))).is_ok());
+
+        assert_eq!(io.write(1u8), 1u8);
+        assert_eq!(io.write(0u8), 1u8); // Save fails
+        assert_eq!(io.select(1u8), 0u8);
+        assert_eq!(io.write(1u8), 1u8);
+        assert_eq!(io.write(0u8), 1u8); // Save fails
+        assert!(!outside.join("file.test").exists());
+
+        let _ = remove_dir_all(SANDBOX);
+        let _ = remove_dir_all(OUTSIDE);
+    }
+
+    #[test]
+    fn test_disabled() {
+        const TEST_FILE : &str = "fileio_test_disabled.test";
+
+        delete_file(TEST_FILE);
+
+        let mut io : FileIo<u8> = FileIo::new(TEST_FILE);
+        assert!(io.set_access(FileAccess::Disabled).is_ok());
+
+        assert_eq!(io.write(1u8), 1u8);
+        assert_eq!(io.write(0u8), 1u8); // Save fails
+        assert_eq!(io.append(0u8), 1u8); // Save fails
+        assert_eq!(io.read(), 1u8);
+        assert_eq!(io.seek(0u8), 1u8);
+        assert!(!Path::new(TEST_FILE).exists());
+    }
+
 }
\ No newline at end of file
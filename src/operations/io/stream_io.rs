@@ -0,0 +1,106 @@
+use crate::operations::io::BraindamageIo;
+use crate::Cell;
+use std::io::{Error, Read, Write};
+
+/// Reads the input byte by byte from `R` and writes the output to `W`. This
+/// is used to redirect the console IO to files or strings.
+pub struct StreamIo<R: Read, W: Write> {
+    reader: R,
+    writer: W,
+    /// The first write error, it's reported by `flush`.
+    error: Option<Error>,
+}
+
+impl<R, W, T> BraindamageIo<T> for StreamIo<R, W>
+    where
+        R: Read,
+        W: Write,
+        T: Cell
+{
+    fn read(&mut self) -> T {
+        let mut byte = [0u8; 1];
+        match self.reader.read_exact(&mut byte) {
+            Ok(_) => byte[0].into(),
+            Err(_) => T::default(),
+        }
+    }
+
+    fn write(&mut self, value: T) -> T {
+        let c = value.to_char();
+        if let Err(error) = write!(self.writer, "{}", c) {
+            self.error.get_or_insert(error);
+        }
+        value
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        self.writer.flush()
+    }
+}
+
+impl<R, W> StreamIo<R, W>
+    where
+        R: Read,
+        W: Write
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        StreamIo {
+            reader,
+            writer,
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let mut io = StreamIo::new("xF".as_bytes(), Vec::new());
+
+        assert_eq!(BraindamageIo::<u8>::read(&mut io), b'x');
+        assert_eq!(BraindamageIo::<u8>::read(&mut io), b'F');
+        // The end of the stream is indicated by 0
+        assert_eq!(BraindamageIo::<u8>::read(&mut io), 0u8);
+        assert_eq!(BraindamageIo::<u8>::read(&mut io), 0u8);
+
+        let mut io = StreamIo::new("xF".as_bytes(), Vec::new());
+
+        assert_eq!(BraindamageIo::<u32>::read(&mut io), u32::from(b'x'));
+        assert_eq!(BraindamageIo::<u32>::read(&mut io), u32::from(b'F'));
+        assert_eq!(BraindamageIo::<u32>::read(&mut io), 0u32);
+    }
+
+    #[test]
+    fn test_write() {
+        let mut output = Vec::new();
+        {
+            let mut io = StreamIo::new("".as_bytes(), &mut output);
+
+            assert_eq!(io.write(b'H'), b'H');
+            assert_eq!(io.write(b'i'), b'i');
+            assert_eq!(io.write(b'\n'), b'\n');
+        }
+
+        assert_eq!(output, b"Hi\n");
+    }
+
+    #[test]
+    fn test_write_error() {
+        let mut buffer = [0u8; 2];
+        let mut io = StreamIo::new("".as_bytes(), &mut buffer[..]);
+
+        for c in b"abc" {
+            io.write(*c);
+        }
+        // The first error is reported once
+        assert!(BraindamageIo::<u8>::flush(&mut io).is_err());
+        assert!(BraindamageIo::<u8>::flush(&mut io).is_ok());
+    }
+}
@@ -1,5 +1,6 @@
 use crate::operations::io::BraindamageIo;
 use crate::Cell;
+use std::sync::{Arc, Mutex};
 
 pub struct TestIo<T: Cell> {
     read_data: Vec<T>,
@@ -7,7 +8,9 @@ pub struct TestIo<T: Cell> {
     loop_read_data: bool,
 
     expected_data: Vec<T>,
-    expected_data_index: usize
+    expected_data_index: usize,
+    /// The written values are recorded here instead of being compared.
+    recorded_data: Option<Arc<Mutex<Vec<T>>>>,
 }
 
 impl<T> BraindamageIo<T> for TestIo<T>
@@ -23,12 +26,14 @@ impl<T> BraindamageIo<T> for TestIo<T>
             }
         }
 
-        self.read_index = self.read_index + 1;
+        self.read_index += 1;
         self.read_data[self.read_index - 1]
     }
 
     fn write(&mut self, value: T) -> T {
-        if self.expected_data_index < self.expected_data.len() {
+        if let Some(recorded) = &self.recorded_data {
+            recorded.lock().unwrap().push(value);
+        } else if self.expected_data_index < self.expected_data.len() {
             self.expected_data_index += 1;
             let expect = self.expected_data[self.expected_data_index - 1];
             assert_eq!(value, expect);
@@ -50,10 +55,21 @@ impl<T> TestIo<T>
             read_index: 0,
             loop_read_data,
             expected_data,
-            expected_data_index: 0
+            expected_data_index: 0,
+            recorded_data: None,
         }
     }
 
+    /// Creates an IO that records the written values. The values can be read
+    /// from the returned vec while the IO is used by an interpreter.
+    #[cfg(test)]
+    pub fn recording(read_data: Vec<T>, loop_read_data: bool) -> (Self, Arc<Mutex<Vec<T>>>) {
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let mut io = TestIo::new(read_data, loop_read_data, Vec::new());
+        io.recorded_data = Some(recorded.clone());
+        (io, recorded)
+    }
+
     #[allow(dead_code)]
     pub fn is_done(&self) -> bool {
         self.is_read_done() && self.is_write_done()
@@ -177,4 +193,13 @@ mod tests {
         assert!(std::panic::catch_unwind(move || io.write(0u8)).is_err());
     }
 
+    #[test]
+    fn test_recording() {
+        let (mut io, recorded) = TestIo::<u8>::recording(vec![7], true);
+        assert_eq!(io.read(), 7u8);
+        assert_eq!(io.write(1u8), 1u8);
+        assert_eq!(io.write(2u8), 2u8);
+        assert_eq!(*recorded.lock().unwrap(), vec![1, 2]);
+    }
+
 }
\ No newline at end of file
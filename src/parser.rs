@@ -1,39 +1,70 @@
 use crate::{Instruction, Cell};
+use crate::buffer::TapeKind;
 use std::fmt::Debug;
 
 pub mod token;
-pub use token::Token;
+pub use token::{Token, Span};
 
 mod mode;
-pub use mode::ParserMode;
+pub use mode::{ParserMode, Pass};
 
 mod lexer;
-pub(crate) use lexer::Lexer;
+pub(crate) use lexer::{find_deep_block, Lexer};
 
-pub(crate) trait Processor<T> {
-    fn process(code: T) -> T;
+mod preprocessor;
+pub use preprocessor::{Preprocessor, Source};
+
+mod macros;
+pub use macros::MacroProcessor;
+
+pub(crate) mod analysis;
+mod evaluator;
+
+/// Returns `true` if the line is handled before the lexer, like an include
+/// or a macro definition. These lines have to be kept as they are.
+pub(crate) fn is_directive_line(line: &str) -> bool {
+    preprocessor::is_include(line) || macros::is_definition(line)
 }
 
 pub(crate) trait Postprocessor<T: Cell>: Debug{
     fn process(&self, code: Vec<Token<T>>) -> Vec<Token<T>>;
 }
+
 mod postprocessor;
 
+/// The bound of the steps that are executed at compile time.
+const PARTIAL_EVAL_STEPS: usize = 1_000_000;
+
+/// The deepest nesting of loops and procedures. The lexer, the passes and the
+/// evaluator recurse into the blocks, deeper code could overflow the stack.
+pub const MAX_NESTING: usize = 512;
+
+pub(crate) mod printer;
+pub use printer::EmitFormat;
+
 #[derive(Debug)]
 pub(crate) struct Parser<T: Cell> {
     mode: ParserMode,
+    /// The size of the tape if it wraps around, the passes can't tell the
+    /// cells apart that are this far away.
+    wrap: Option<usize>,
     lexer: Lexer<T>,
-    postprocessor: Vec<Box<dyn Postprocessor<T>>>
+    postprocessor: Vec<(Pass, Box<dyn Postprocessor<T>>)>,
+    /// The tokens are printed to stderr after this pass.
+    dump_after: Option<(Pass, EmitFormat)>,
 }
 
 impl<T: Cell> Parser<T> {
 
-    pub fn new(mode: ParserMode) -> Self {
-        let lexer = Lexer::new(mode.keep_comments());
+    /// Creates the parser for code that runs on a tape of the kind and size.
+    pub fn new(mode: ParserMode, tape: TapeKind, size: usize) -> Self {
+        let lexer = Lexer::new();
         let mut pipe = Parser {
             mode,
+            wrap: tape.wrap_size(size),
             lexer,
             postprocessor: Vec::new(),
+            dump_after: None,
         };
 
         pipe.setup();
@@ -42,19 +73,93 @@ impl<T: Cell> Parser<T> {
     }
 
     fn setup(&mut self) {
-        if self.mode.aggregate_instructions() {
-            self.postprocessor.push(Box::new(postprocessor::AggregateSameProcessor::create()))
+        for pass in self.mode.passes() {
+            self.postprocessor.push((pass, pass.create(self.wrap)));
         }
     }
 
+    /// Prints the code to stderr after the pass was executed.
+    pub fn set_dump_after(&mut self, pass: Pass, format: EmitFormat) {
+        self.dump_after = Some((pass, format));
+    }
+
+    #[cfg(any(test, feature = "fuzzing"))]
     pub fn parse_script(&mut self, script: &str) -> Vec<Instruction<T>> {
+        let tokens = self.parse_tokens(script);
+        self.flatten(tokens)
+    }
+
+    /// Turns the tokens into the instructions of the interpreter.
+    pub fn flatten(&self, tokens: Vec<Token<T>>) -> Vec<Instruction<T>> {
+        self.lexer.flatten(tokens)
+    }
+
+    /// Lexes the script and runs the postprocessors on the tokens.
+    pub fn parse_tokens(&mut self, script: &str) -> Vec<Token<T>> {
         let mut tokens = self.lexer.lex_string(script);
 
-        for i in 0..self.postprocessor.len() {
-            tokens = self.postprocessor.get(i).unwrap().process(tokens);
+        for (pass, postprocessor) in self.postprocessor.iter() {
+            tokens = postprocessor.process(tokens);
+
+            if let Some((dump_pass, format)) = self.dump_after {
+                if dump_pass == *pass {
+                    eprint!("After {}:\n{}", pass, printer::emit(&tokens, format));
+                }
+            }
         }
 
-        self.lexer.flatten(tokens)
+        tokens
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comments() {
+        let mut lexer: Lexer<u8> = Lexer::new();
+        let tokens = lexer.lex_string("ab+ c[x]_");
+        let comments: Vec<(&str, Span)> = tokens.iter()
+            .filter_map(|token| match token {
+                Token::Comment(text, span) => Some((text.as_str(), *span)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(comments, vec![("ab", Span::new(0, 2)), (" c", Span::new(3, 5))]);
+        assert_eq!(tokens[3].span(), Span::new(5, 8));
+
+        // Comments don't produce instructions in both modes
+        let code: Vec<Instruction<u8>> = Parser::new(ParserMode::ALL, TapeKind::Vec, 16).parse_script("ab+ c+[x]_");
+        assert_eq!(code, vec![
+            Instruction::IncreaseValue(2),
+            Instruction::LoopStart(1),
+            Instruction::LoopEnd(1),
+            Instruction::NoOp,
+        ]);
+        let code: Vec<Instruction<u8>> = Parser::new(ParserMode::DEBUG, TapeKind::Vec, 16).parse_script("ab+ c+[x]_");
+        assert_eq!(code.len(), 5);
+
+        // A NUL char is a comment like any other char
+        let tokens: Vec<Token<u8>> = Lexer::new().lex_string("+\0+");
+        assert_eq!(tokens.len(), 3);
+    }
+
+    #[test]
+    fn test_nesting() {
+        assert_eq!(find_deep_block("[[]][([])]", 2), Some(6));
+        assert_eq!(find_deep_block("[[]][(x)]", 2), None);
+        // Unmatched ends are comments
+        assert_eq!(find_deep_block("[)[[]])]", 2), Some(3));
+        assert_eq!(find_deep_block("]])[", 1), None);
+
+        let code = format!("+{}-{}", "[".repeat(MAX_NESTING), "]".repeat(MAX_NESTING));
+        assert_eq!(find_deep_block(&code, MAX_NESTING), None);
+        assert_eq!(find_deep_block(&format!("[{}", code), MAX_NESTING), Some(MAX_NESTING + 1));
+        for level in 0..=ParserMode::MAX_LEVEL {
+            let instructions: Vec<Instruction<u8>> = Parser::new(ParserMode::level(level), TapeKind::Infinite, 16).parse_script(&code);
+            assert!(instructions.len() <= 2 * MAX_NESTING + 2);
+        }
+    }
+}
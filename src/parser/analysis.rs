@@ -0,0 +1,266 @@
+use crate::parser::Token;
+use crate::{Cell, Instruction};
+use std::collections::{HashMap, HashSet};
+
+/// The statically known values of the tape at a point in the program.
+///
+/// The cells are addressed by their offset to an anchor, which is the start
+/// of the program or the position of the pointer after it was lost. The
+/// values are tracked through straight-line code and loops that return to
+/// the same pointer position. On a tape that wraps around, the offsets of
+/// the same cell differ by the tape size. The cells are unknown when the
+/// changed cells and the read cell could span the whole tape.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct State<T: Cell> {
+    /// The values of the cells that were changed, `None` if it's unknown.
+    cells: HashMap<isize, Option<T>>,
+    pointer: isize,
+    /// The value of the cells that are not in `cells`.
+    rest: Option<T>,
+    /// The lowest and the highest offset in `cells`.
+    changed: Option<(isize, isize)>,
+    /// The size of the tape if it wraps around.
+    wrap: Option<usize>,
+}
+
+impl<T: Cell> State<T> {
+    /// The state at the start of the program, every cell is zero.
+    pub fn start(wrap: Option<usize>) -> Self {
+        State {
+            rest: Some(T::default()),
+            ..State::unknown(wrap)
+        }
+    }
+
+    /// A state without any known values.
+    pub fn unknown(wrap: Option<usize>) -> Self {
+        State {
+            cells: HashMap::new(),
+            pointer: 0,
+            rest: None,
+            changed: None,
+            wrap,
+        }
+    }
+
+    /// Returns the offsets of the changed cells including the position.
+    fn changed_with(&self, position: isize) -> (isize, isize) {
+        self.changed.map_or((position, position), |(low, high)| (low.min(position), high.max(position)))
+    }
+
+    /// Returns `true` if the position could be the same cell as a changed
+    /// cell at another offset.
+    fn can_wrap(&self, position: isize) -> bool {
+        let (low, high) = self.changed_with(position);
+        self.wrap.is_some_and(|size| (high - low).unsigned_abs() >= size)
+    }
+
+    /// Returns the value of the cell at the offset to the pointer.
+    pub fn value(&self, offset: isize) -> Option<T> {
+        let position = self.pointer + offset;
+        if self.can_wrap(position) {
+            return None;
+        }
+
+        self.cells.get(&position).copied().unwrap_or(self.rest)
+    }
+
+    /// Returns the value of the cell at the pointer.
+    pub fn current(&self) -> Option<T> {
+        self.value(0)
+    }
+
+    /// Returns `true` if the cell at the pointer is known to be zero.
+    pub fn is_zero(&self) -> bool {
+        self.current() == Some(T::default())
+    }
+
+    fn set(&mut self, offset: isize, value: Option<T>) {
+        let position = self.pointer + offset;
+        if self.can_wrap(position) {
+            self.cells.clear();
+            self.rest = None;
+            self.changed = None;
+        }
+
+        self.changed = Some(self.changed_with(position));
+        self.cells.insert(position, value);
+    }
+
+    /// Updates the state with the effect of the token.
+    pub fn apply(&mut self, token: &Token<T>) {
+        match token {
+            Token::Instruction(inst, _) => self.apply_instruction(inst),
+            Token::LoopBlock(content, _) => {
+                if self.is_zero() {
+                    return;
+                }
+
+                match loop_writes(content) {
+                    Some(writes) => {
+                        for offset in writes {
+                            self.set(offset, None);
+                        }
+                    },
+                    None => *self = State::unknown(self.wrap),
+                }
+                self.set(0, Some(T::default()));
+            },
+            // A definition is skipped
+            Token::ProcedureBlock(..) | Token::Comment(..) => {},
+        }
+    }
+
+    fn apply_instruction(&mut self, inst: &Instruction<T>) {
+        match inst {
+            Instruction::IncreaseValue(x) => self.set(0, self.current().map(|value| value.add_overflow(x))),
+            Instruction::DecreaseValue(x) => self.set(0, self.current().map(|value| value.sub_overflow(x))),
+            Instruction::IncreaseIndex(x) => self.pointer += *x as isize,
+            Instruction::DecreaseIndex(x) => self.pointer -= *x as isize,
+            Instruction::NoOp | Instruction::IoWrite => {},
+            // A failed save replaces the submitted zero by one
+            Instruction::FileIoWrite | Instruction::FileIoAppend => {
+                if self.is_zero() {
+                    self.set(0, None);
+                }
+            },
+            Instruction::IoRead | Instruction::FileIoRead | Instruction::FileSelect | Instruction::FileSeek => {
+                self.set(0, None);
+            },
+            // Another bank or a procedure can change anything
+            Instruction::BankSwitch | Instruction::CallProcedure
+            | Instruction::LoopStart(_) | Instruction::LoopEnd(_)
+            | Instruction::ProcedureStart(_) | Instruction::ProcedureEnd => *self = State::unknown(self.wrap),
+        }
+    }
+
+    /// Returns the state at the start of every iteration of the loop. The
+    /// cells that are changed by the loop are unknown.
+    pub fn enter_loop(&self, content: &[Token<T>]) -> State<T> {
+        let mut state = match loop_writes(content) {
+            Some(writes) => {
+                let mut state = self.clone();
+                for offset in writes {
+                    state.set(offset, None);
+                }
+                state
+            },
+            None => State::unknown(self.wrap),
+        };
+
+        state.set(0, None);
+        state
+    }
+}
+
+/// Returns the offsets of the cells that are changed by one iteration of the
+/// loop body. `None` if the loop doesn't return to the same position or calls
+/// code that can change anything.
+pub(crate) fn loop_writes<T: Cell>(content: &[Token<T>]) -> Option<HashSet<isize>> {
+    let mut writes = HashSet::new();
+    let mut offset = 0;
+
+    for token in content {
+        match token {
+            Token::Instruction(inst, _) => match inst {
+                Instruction::IncreaseIndex(x) => offset += *x as isize,
+                Instruction::DecreaseIndex(x) => offset -= *x as isize,
+                Instruction::NoOp | Instruction::IoWrite => {},
+                Instruction::BankSwitch | Instruction::CallProcedure
+                | Instruction::LoopStart(_) | Instruction::LoopEnd(_)
+                | Instruction::ProcedureStart(_) | Instruction::ProcedureEnd => return None,
+                _ => {
+                    writes.insert(offset);
+                },
+            },
+            Token::LoopBlock(content, _) => {
+                writes.extend(loop_writes(content)?.into_iter().map(|inner| offset + inner));
+            },
+            Token::ProcedureBlock(..) | Token::Comment(..) => {},
+        }
+    }
+
+    if offset != 0 {
+        return None;
+    }
+
+    Some(writes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Lexer;
+
+    fn analyze(code: &str) -> State<u8> {
+        analyze_on(code, None)
+    }
+
+    fn analyze_on(code: &str, wrap: Option<usize>) -> State<u8> {
+        let mut state = State::start(wrap);
+        for token in Lexer::new().lex_string(code).iter() {
+            state.apply(token);
+        }
+        state
+    }
+
+    #[test]
+    fn test_straight_line() {
+        let state = analyze("+++>--<<,>.");
+        assert_eq!(state.current(), Some(3));
+        assert_eq!(state.value(1), Some(254));
+        assert_eq!(state.value(-1), None);
+        assert_eq!(state.value(5), Some(0));
+    }
+
+    #[test]
+    fn test_loops() {
+        // A balanced loop clears the current cell and changes the cells in the body
+        let state = analyze("+>+++>++<<[->+<]");
+        assert_eq!(state.current(), Some(0));
+        assert_eq!(state.value(1), None);
+        assert_eq!(state.value(2), Some(2));
+
+        // The pointer is lost after an unbalanced loop
+        let state = analyze("+>++<[>]");
+        assert_eq!(state.current(), Some(0));
+        assert_eq!(state.value(1), None);
+
+        // A dead loop doesn't change anything
+        let state = analyze(">+<[>-<]");
+        assert_eq!(state.value(1), Some(1));
+
+        let tokens: Vec<Token<u8>> = Lexer::new().lex_string("[->+<]");
+        if let Token::LoopBlock(content, _) = &tokens[0] {
+            let state = analyze("++>>+<<").enter_loop(content);
+            assert_eq!(state.current(), None);
+            assert_eq!(state.value(1), None);
+            assert_eq!(state.value(2), Some(1));
+        }
+    }
+
+    #[test]
+    fn test_wrapping_tape() {
+        // The offsets 0 and 4 are the same cell on a tape with 4 cells
+        let state = analyze_on("+>>>>", Some(4));
+        assert_eq!(state.current(), None);
+        assert_eq!(state.value(-1), Some(0));
+        assert_eq!(state.value(-4), Some(1));
+        assert_eq!(analyze_on("+>>>>", Some(5)).current(), Some(0));
+
+        // The cells are forgotten when a change reaches around the tape
+        let state = analyze_on("+>++>>>+", Some(4));
+        assert_eq!(state.current(), None);
+        assert_eq!(state.value(-3), None);
+        assert_eq!(state.value(-1), None);
+        let state = analyze_on("+>>,[>>>+<<<-]", Some(4));
+        assert_eq!(state.current(), Some(0));
+        assert_eq!(state.value(-2), None);
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(analyze("+>+%").value(-1), None);
+        assert_eq!(analyze("+(-)").current(), Some(1));
+    }
+}
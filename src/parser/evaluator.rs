@@ -0,0 +1,204 @@
+use crate::parser::{Span, Token};
+use crate::{Cell, Instruction};
+use std::collections::BTreeMap;
+
+/// The largest number of cells that are written at compile time. This keeps
+/// the emitted code small.
+const MAX_CELLS: usize = 4096;
+
+/// The reason why the evaluation stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Stop {
+    /// The token depends on input, files, banks or procedures.
+    Effect,
+    /// The step bound or the cell bound was reached, or the pointer could
+    /// reach around a wrapping tape.
+    Limit,
+}
+
+/// Executes tokens at compile time. Only the operations that don't depend on
+/// the environment are executed, which are the pointer and value changes,
+/// loops and the output with `.`.
+#[derive(Debug, Clone)]
+pub(crate) struct Evaluator<T: Cell> {
+    /// The cells by their offset to the start position.
+    cells: BTreeMap<isize, T>,
+    pointer: isize,
+    /// The lowest and the highest offset the pointer visited.
+    visited: (isize, isize),
+    output: Vec<T>,
+    steps: usize,
+    max_steps: usize,
+    /// The size of a tape that wraps around, `None` if it grows instead.
+    wrap: Option<usize>,
+}
+
+impl<T: Cell> Evaluator<T> {
+    pub fn new(max_steps: usize, wrap: Option<usize>) -> Self {
+        Evaluator {
+            cells: BTreeMap::new(),
+            pointer: 0,
+            visited: (0, 0),
+            output: Vec::new(),
+            steps: 0,
+            max_steps,
+            wrap,
+        }
+    }
+
+    fn current(&self) -> T {
+        self.cells.get(&self.pointer).copied().unwrap_or_default()
+    }
+
+    fn step(&mut self) -> Result<(), Stop> {
+        self.steps += 1;
+        if self.steps > self.max_steps || self.cells.len() > MAX_CELLS {
+            return Err(Stop::Limit);
+        }
+
+        Ok(())
+    }
+
+    /// Moves the pointer to the offset. Stops before the visited offsets span
+    /// the whole wrapping tape, two offsets could be the same cell then.
+    fn move_to(&mut self, pointer: isize) -> Result<(), Stop> {
+        let visited = (self.visited.0.min(pointer), self.visited.1.max(pointer));
+        if self.wrap.is_some_and(|size| (visited.1 - visited.0).unsigned_abs() >= size) {
+            return Err(Stop::Limit);
+        }
+
+        self.pointer = pointer;
+        self.visited = visited;
+        Ok(())
+    }
+
+    /// Executes the tokens. The state is undefined after an error.
+    pub fn run(&mut self, tokens: &[Token<T>]) -> Result<(), Stop> {
+        for token in tokens {
+            match token {
+                Token::Instruction(inst, _) => {
+                    self.step()?;
+                    match inst {
+                        Instruction::IncreaseValue(x) => {
+                            let value = self.current().add_overflow(x);
+                            self.cells.insert(self.pointer, value);
+                        },
+                        Instruction::DecreaseValue(x) => {
+                            let value = self.current().sub_overflow(x);
+                            self.cells.insert(self.pointer, value);
+                        },
+                        Instruction::IncreaseIndex(x) => self.move_to(self.pointer + *x as isize)?,
+                        Instruction::DecreaseIndex(x) => self.move_to(self.pointer - *x as isize)?,
+                        Instruction::IoWrite => self.output.push(self.current()),
+                        Instruction::NoOp => {},
+                        _ => return Err(Stop::Effect),
+                    }
+                },
+                Token::LoopBlock(content, _) => {
+                    self.step()?;
+                    while self.current() != T::default() {
+                        self.run(content)?;
+                        self.step()?;
+                    }
+                },
+                // A definition depends on the procedure table of the interpreter
+                Token::ProcedureBlock(..) => return Err(Stop::Effect),
+                Token::Comment(..) => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns tokens that write the output and leave the tape in the
+    /// evaluated state.
+    pub fn emit(&self, span: Span) -> Vec<Token<T>> {
+        let mut tokens = Vec::new();
+
+        // The first cell is used to write the output
+        let mut value = T::default();
+        for output in self.output.iter().copied() {
+            push_change(&mut tokens, value, output, span);
+            tokens.push(Token::Instruction(Instruction::IoWrite, span));
+            value = output;
+        }
+
+        let mut pointer = 0;
+        for (offset, cell) in self.cells.iter() {
+            push_move(&mut tokens, pointer, *offset, span);
+            pointer = *offset;
+            let from = if *offset == 0 { value } else { T::default() };
+            push_change(&mut tokens, from, *cell, span);
+            if *offset == 0 {
+                value = *cell;
+            }
+        }
+        if !self.cells.contains_key(&0) {
+            push_move(&mut tokens, pointer, 0, span);
+            pointer = 0;
+            push_change(&mut tokens, value, T::default(), span);
+        }
+        push_move(&mut tokens, pointer, self.pointer, span);
+
+        tokens
+    }
+}
+
+/// Pushes the shorter of `+` and `-` to change the value.
+fn push_change<T: Cell>(tokens: &mut Vec<Token<T>>, from: T, to: T, span: Span) {
+    let up = to.sub_overflow(&from);
+    let down = from.sub_overflow(&to);
+    if up == T::default() {
+        return;
+    }
+
+    let inst = if up.to_usize() <= down.to_usize() {
+        Instruction::IncreaseValue(up)
+    } else {
+        Instruction::DecreaseValue(down)
+    };
+    tokens.push(Token::Instruction(inst, span));
+}
+
+fn push_move<T: Cell>(tokens: &mut Vec<Token<T>>, from: isize, to: isize, span: Span) {
+    let inst = match to - from {
+        0 => return,
+        offset if offset > 0 => Instruction::IncreaseIndex(offset as usize),
+        offset => Instruction::DecreaseIndex(offset.unsigned_abs()),
+    };
+    tokens.push(Token::Instruction(inst, span));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{printer, Lexer};
+
+    fn evaluate(code: &str) -> (Result<(), Stop>, String) {
+        evaluate_on(code, None)
+    }
+
+    fn evaluate_on(code: &str, wrap: Option<usize>) -> (Result<(), Stop>, String) {
+        let mut evaluator: Evaluator<u8> = Evaluator::new(1000, wrap);
+        let result = evaluator.run(&Lexer::new().lex_string(code));
+        (result, printer::print_tokens(&evaluator.emit(Span::default())))
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(evaluate("++[>+++<-]>."), (Ok(()), "++++++.------>++++++".to_string()));
+        assert_eq!(evaluate("<<+>>"), (Ok(()), "<<+>>".to_string()));
+        assert_eq!(evaluate("+.+.>"), (Ok(()), "+.+.>".to_string()));
+        assert_eq!(evaluate("+,"), (Err(Stop::Effect), "+".to_string()));
+        assert_eq!(evaluate("+[]").0, Err(Stop::Limit));
+    }
+
+    #[test]
+    fn test_wrapping_tape() {
+        // The offsets 0 and 4 are the same cell on a tape with 4 cells
+        assert_eq!(evaluate_on("+>>>>+.", Some(4)), (Err(Stop::Limit), "+>>>".to_string()));
+        assert_eq!(evaluate_on("+>>>>+.", Some(5)), (Ok(()), "+.>>>>+".to_string()));
+        assert_eq!(evaluate_on("+<<<<+.", Some(4)).0, Err(Stop::Limit));
+        assert_eq!(evaluate_on(">>>>>>>>+.", None).0, Ok(()));
+    }
+}
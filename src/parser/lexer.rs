@@ -1,74 +1,98 @@
 use crate::{Cell, Instruction};
-use std::str::Chars;
-use crate::parser::Token;
+use std::str::CharIndices;
+use crate::parser::{Token, Span};
 use std::marker::PhantomData;
 
-const OP_INC_INDEX: char = '>';
-const OP_DEC_INDEX: char = '<';
-const OP_INC_VALUE: char = '+';
-const OP_DEC_VALUE: char = '-';
-const OP_IO_READ: char = ',';
-const OP_IO_WRITE: char = '.';
-const OP_LOOP_START: char = '[';
-const OP_LOOP_END: char = ']';
-const OP_FILE_IO_READ: char = ';';
-const OP_FILE_IO_WRITE: char = ':';
-const OP_NOOP: char = '_';
+pub(crate) const OP_INC_INDEX: char = '>';
+pub(crate) const OP_DEC_INDEX: char = '<';
+pub(crate) const OP_INC_VALUE: char = '+';
+pub(crate) const OP_DEC_VALUE: char = '-';
+pub(crate) const OP_IO_READ: char = ',';
+pub(crate) const OP_IO_WRITE: char = '.';
+pub(crate) const OP_LOOP_START: char = '[';
+pub(crate) const OP_LOOP_END: char = ']';
+pub(crate) const OP_FILE_IO_READ: char = ';';
+pub(crate) const OP_FILE_IO_WRITE: char = ':';
+pub(crate) const OP_FILE_SELECT: char = '#';
+pub(crate) const OP_FILE_IO_APPEND: char = '&';
+pub(crate) const OP_FILE_SEEK: char = '=';
+pub(crate) const OP_BANK_SWITCH: char = '|';
+pub(crate) const OP_PROCEDURE_START: char = '(';
+pub(crate) const OP_PROCEDURE_END: char = ')';
+pub(crate) const OP_CALL_PROCEDURE: char = '%';
+pub(crate) const OP_NOOP: char = '_';
 
+/// Lexes the code into tokens. All chars that are not operators are kept as
+/// `Token::Comment`, consecutive chars are joined into one comment.
 #[derive(Debug)]
 pub(crate) struct Lexer<T: Cell> {
-    unknown_to_noop: bool,
     phantom: PhantomData<T>,
 }
 
 impl<T> Lexer<T>
     where T: Cell
 {
-    pub fn new(unknown_to_noop: bool) -> Self {
+    pub fn new() -> Self {
         Lexer {
-            unknown_to_noop,
             phantom: PhantomData
         }
     }
 
     pub fn lex_string(&mut self, code: &str) -> Vec<Token<T>> {
-        let code = code.to_string();
-        self.lex_block(&mut code.chars(), char::default())
+        let (block, _) = self.lex_block(&mut code.char_indices(), None, code.len());
+        block
     }
 
-    fn lex_block(&mut self, code: &mut Chars, end: char) -> Vec<Token<T>> {
+    /// Lexes the tokens until `end` is found. Returns the block and the end
+    /// of its last char, `code_len` is used if the block isn't closed.
+    fn lex_block(&mut self, code: &mut CharIndices, end: Option<char>, code_len: usize) -> (Vec<Token<T>>, usize) {
         let mut block = Vec::new();
 
         loop {
             let inst = code.next();
             if inst.is_none() {
-                return block;
+                return (block, code_len);
             }
 
-            let inst = inst.unwrap();
-            if inst == end {
-                return block;
+            let (start, inst) = inst.unwrap();
+            if Some(inst) == end {
+                return (block, start + inst.len_utf8());
             }
 
+            let span = Span::new(start, start + inst.len_utf8());
             let lexed_inst = match inst {
-                OP_INC_INDEX     => {Some(Token::Instruction(Instruction::IncreaseIndex(1)))},
-                OP_DEC_INDEX     => {Some(Token::Instruction(Instruction::DecreaseIndex(1)))},
-                OP_INC_VALUE     => {Some(Token::Instruction(Instruction::IncreaseValue(T::from(1u8))))},
-                OP_DEC_VALUE     => {Some(Token::Instruction(Instruction::DecreaseValue(T::from(1u8))))},
-                OP_IO_READ       => {Some(Token::Instruction(Instruction::IoRead))},
-                OP_IO_WRITE      => {Some(Token::Instruction(Instruction::IoWrite))},
+                OP_INC_INDEX     => {Some(Token::Instruction(Instruction::IncreaseIndex(1), span))},
+                OP_DEC_INDEX     => {Some(Token::Instruction(Instruction::DecreaseIndex(1), span))},
+                OP_INC_VALUE     => {Some(Token::Instruction(Instruction::IncreaseValue(T::from(1u8)), span))},
+                OP_DEC_VALUE     => {Some(Token::Instruction(Instruction::DecreaseValue(T::from(1u8)), span))},
+                OP_IO_READ       => {Some(Token::Instruction(Instruction::IoRead, span))},
+                OP_IO_WRITE      => {Some(Token::Instruction(Instruction::IoWrite, span))},
                 OP_LOOP_START    => {
-                    Some(Token::LoopBlock(self.lex_block(code, OP_LOOP_END)))
+                    let (content, block_end) = self.lex_block(code, Some(OP_LOOP_END), code_len);
+                    Some(Token::LoopBlock(content, Span::new(start, block_end)))
                 },
-                OP_FILE_IO_READ  => {Some(Token::Instruction(Instruction::FileIoRead))},
-                OP_FILE_IO_WRITE => {Some(Token::Instruction(Instruction::FileIoWrite))},
-                OP_NOOP          => {Some(Token::Instruction(Instruction::NoOp))}
+                OP_FILE_IO_READ  => {Some(Token::Instruction(Instruction::FileIoRead, span))},
+                OP_FILE_IO_WRITE => {Some(Token::Instruction(Instruction::FileIoWrite, span))},
+                OP_FILE_SELECT   => {Some(Token::Instruction(Instruction::FileSelect, span))},
+                OP_FILE_IO_APPEND => {Some(Token::Instruction(Instruction::FileIoAppend, span))},
+                OP_FILE_SEEK     => {Some(Token::Instruction(Instruction::FileSeek, span))},
+                OP_BANK_SWITCH   => {Some(Token::Instruction(Instruction::BankSwitch, span))},
+                OP_PROCEDURE_START => {
+                    let (content, block_end) = self.lex_block(code, Some(OP_PROCEDURE_END), code_len);
+                    Some(Token::ProcedureBlock(content, Span::new(start, block_end)))
+                },
+                OP_CALL_PROCEDURE => {Some(Token::Instruction(Instruction::CallProcedure, span))},
+                OP_NOOP          => {Some(Token::Instruction(Instruction::NoOp, span))}
                 _ => {
-                    if self.unknown_to_noop {
-                        Some(Token::Instruction(Instruction::NoOp))
-                    } else {
-                        None
+                    if let Some(Token::Comment(text, comment_span)) = block.last_mut() {
+                        if comment_span.end == start {
+                            text.push(inst);
+                            *comment_span = comment_span.join(span);
+                            continue;
+                        }
                     }
+
+                    Some(Token::Comment(inst.to_string(), span))
                 }
             };
 
@@ -86,8 +110,9 @@ impl<T> Lexer<T>
 
         for item in items {
             match item {
-                Token::Instruction(x) => {instructions.push(x)},
-                Token::LoopBlock(block) => {
+                Token::Instruction(x, _) => {instructions.push(x)},
+                Token::Comment(_, _) => {},
+                Token::LoopBlock(block, _) => {
                     let mut block = self.flatten(block);
                     let block_size = block.len() + 1;
 
@@ -95,6 +120,13 @@ impl<T> Lexer<T>
                     instructions.append(&mut block);
                     instructions.push(Instruction::LoopEnd(block_size));
                 },
+                Token::ProcedureBlock(block, _) => {
+                    let mut block = self.flatten(block);
+
+                    instructions.push(Instruction::ProcedureStart(block.len() + 1));
+                    instructions.append(&mut block);
+                    instructions.push(Instruction::ProcedureEnd);
+                },
             }
         }
 
@@ -102,10 +134,33 @@ impl<T> Lexer<T>
     }
 }
 
+/// Returns the start of the first block that is nested deeper than `max_depth`.
+/// The blocks are matched like the lexer does, a `)` in a loop is a comment.
+pub(crate) fn find_deep_block(code: &str, max_depth: usize) -> Option<usize> {
+    let mut ends = Vec::new();
+
+    for (start, c) in code.char_indices() {
+        match c {
+            OP_LOOP_START | OP_PROCEDURE_START => {
+                ends.push(if c == OP_LOOP_START { OP_LOOP_END } else { OP_PROCEDURE_END });
+                if ends.len() > max_depth {
+                    return Some(start);
+                }
+            },
+            _ if ends.last() == Some(&c) => {
+                ends.pop();
+            },
+            _ => {},
+        }
+    }
+
+    None
+}
+
 impl<T> Default for Lexer<T>
     where T: Cell
 {
     fn default() -> Self {
-        Lexer::new(false)
+        Lexer::new()
     }
 }
@@ -0,0 +1,219 @@
+use crate::parser::Source;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::iter::Peekable;
+use std::str::Chars;
+
+const MACRO_PREFIX: char = '$';
+const REPEAT: char = '*';
+/// The largest repeat count, this prevents huge allocations by a typo.
+const MAX_REPEAT: usize = 1 << 20;
+/// The largest length of all expanded text. Nested macros with repeats would
+/// otherwise grow exponentially.
+const MAX_EXPANSION: usize = 1 << 26;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacroError {
+    /// A line starting with `$name =` without the closing `;`.
+    InvalidDefinition(String),
+    Undefined {
+        name: String,
+        location: String,
+    },
+    RepeatTooLarge {
+        count: String,
+        location: String,
+    },
+    /// The expanded code is longer than `MAX_EXPANSION`.
+    ExpansionTooLarge(String),
+}
+
+impl Display for MacroError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MacroError::InvalidDefinition(location) => write!(
+                f, "{}: Expected a macro definition like `$name = +>+;`", location),
+            MacroError::Undefined { name, location } => write!(
+                f, "{}: The macro `${}` is not defined", location, name),
+            MacroError::RepeatTooLarge { count, location } => write!(
+                f, "{}: The repeat count {} is larger than {}", location, count, MAX_REPEAT),
+            MacroError::ExpansionTooLarge(location) => write!(
+                f, "{}: The expanded code is longer than {} bytes", location, MAX_EXPANSION),
+        }
+    }
+}
+
+/// Expands the macros in the source to plain Braindamage code:
+///
+/// * `+*16` repeats the previous char or macro 16 times.
+/// * A line `$name = ...;` defines the macro `name`. The body ends at the
+///   last `;` of the line, it's expanded at the definition.
+/// * `$name` is replaced by the body of the macro.
+///
+/// Definitions are replaced by empty lines to keep the line numbers.
+#[derive(Debug, Default)]
+pub struct MacroProcessor {
+    macros: HashMap<String, String>,
+    /// The length of all text that was expanded so far.
+    expanded_len: usize,
+}
+
+impl MacroProcessor {
+    pub fn new() -> Self {
+        MacroProcessor {
+            macros: HashMap::new(),
+            expanded_len: 0,
+        }
+    }
+
+    pub fn expand(&mut self, source: Source) -> Result<Source, MacroError> {
+        source.map_lines(|line, location| {
+            let location = format!("{}:{}", location.file, location.line);
+            let newline = if line.ends_with('\n') { "\n" } else { "" };
+
+            match parse_definition(line) {
+                Some((name, body)) => {
+                    let body = body.ok_or_else(|| MacroError::InvalidDefinition(location.clone()))?;
+                    let body = self.expand_text(body, &location)?;
+                    self.macros.insert(name.to_string(), body);
+                    Ok(newline.to_string())
+                },
+                None => self.expand_text(line, &location),
+            }
+        })
+    }
+
+    fn expand_text(&mut self, text: &str, location: &str) -> Result<String, MacroError> {
+        let mut result = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let name = if c == MACRO_PREFIX { parse_name(&mut chars) } else { String::new() };
+            let expanded = if name.is_empty() {
+                c.to_string()
+            } else {
+                self.macros.get(&name)
+                    .cloned()
+                    .ok_or_else(|| MacroError::Undefined {
+                        name,
+                        location: location.to_string(),
+                    })?
+            };
+
+            let count = parse_repeat(&mut chars, location)?;
+            self.expanded_len = expanded.len().checked_mul(count)
+                .and_then(|len| self.expanded_len.checked_add(len))
+                .filter(|len| *len <= MAX_EXPANSION)
+                .ok_or_else(|| MacroError::ExpansionTooLarge(location.to_string()))?;
+            result.push_str(&expanded.repeat(count));
+        }
+
+        Ok(result)
+    }
+}
+
+/// Returns `true` if the line is a macro definition.
+pub fn is_definition(line: &str) -> bool {
+    parse_definition(line).is_some()
+}
+
+/// Returns the name and the body of a `$name = body;` line. The body is
+/// `None` if the `;` is missing.
+fn parse_definition(line: &str) -> Option<(&str, Option<&str>)> {
+    let line = line.trim_start().strip_prefix(MACRO_PREFIX)?;
+    let name_len = line.find(|c: char| !is_name_char(c)).unwrap_or(line.len());
+    let (name, rest) = line.split_at(name_len);
+    let rest = rest.trim_start().strip_prefix('=')?;
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((name, rest.rfind(';').map(|end| rest[..end].trim())))
+}
+
+fn parse_name(chars: &mut Peekable<Chars>) -> String {
+    let mut name = String::new();
+    while let Some(c) = chars.peek().copied().filter(|c| is_name_char(*c)) {
+        name.push(c);
+        chars.next();
+    }
+
+    name
+}
+
+/// Parses a `*n` repeat count. Returns `1` if there is no repeat count.
+fn parse_repeat(chars: &mut Peekable<Chars>, location: &str) -> Result<usize, MacroError> {
+    if chars.peek() != Some(&REPEAT) {
+        return Ok(1);
+    }
+
+    // The `*` is only a repeat if a number follows
+    let mut lookahead = chars.clone();
+    lookahead.next();
+    let mut count = String::new();
+    while let Some(c) = lookahead.peek().copied().filter(char::is_ascii_digit) {
+        count.push(c);
+        lookahead.next();
+    }
+    if count.is_empty() {
+        return Ok(1);
+    }
+
+    *chars = lookahead;
+    match count.parse::<usize>() {
+        Ok(value) if value <= MAX_REPEAT => Ok(value),
+        _ => Err(MacroError::RepeatTooLarge {
+            count,
+            location: location.to_string(),
+        }),
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Preprocessor;
+
+    fn expand(code: &str) -> Result<String, MacroError> {
+        let source = Preprocessor::default().process(code, None).unwrap();
+        MacroProcessor::new().expand(source).map(|source| source.code)
+    }
+
+    #[test]
+    fn test_repeat() {
+        assert_eq!(expand("+*16."), Ok("++++++++++++++++.".to_string()));
+        assert_eq!(expand(">*2<*0+"), Ok(">>+".to_string()));
+        // A `*` without a number is a comment
+        assert_eq!(expand("a * b+*x"), Ok("a * b+*x".to_string()));
+        assert!(matches!(expand("+*9999999999"), Err(MacroError::RepeatTooLarge { .. })));
+    }
+
+    #[test]
+    fn test_expansion_limit() {
+        let code = "$a = +*1048576;\n$b = $a*1048576;\n$b";
+        assert_eq!(expand(code), Err(MacroError::ExpansionTooLarge("<src>:2".to_string())));
+
+        // The limit applies to the whole source, not every line
+        let code = format!("$a = +*1048576;\n{}", "$a*32\n".repeat(3));
+        assert_eq!(expand(&code), Err(MacroError::ExpansionTooLarge("<src>:3".to_string())));
+    }
+
+    #[test]
+    fn test_definitions() {
+        let code = "$inc = +*2;\n  $print_two = $inc*2.;; comment\n$print_two>$inc\n$$ $";
+        assert_eq!(expand(code), Ok("\n\n++++.;>++\n$$ $".to_string()));
+
+        assert!(matches!(expand("$x = +"), Err(MacroError::InvalidDefinition(_))));
+        match expand("+\n$missing") {
+            Err(MacroError::Undefined { name, location }) => {
+                assert_eq!(name, "missing");
+                assert_eq!(location, "<src>:2");
+            },
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
@@ -1,23 +1,165 @@
-#[derive(Copy, Clone, PartialOrd, PartialEq, Debug)]
-pub enum ParserMode {
-    Debug,
-    Release,
+use crate::Cell;
+use crate::parser::{Postprocessor, PARTIAL_EVAL_STEPS};
+use crate::parser::postprocessor::*;
+use std::fmt::{self, Display, Formatter};
+
+/// The optimizer passes in the order they are executed.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Pass {
+    StripComments,
+    Aggregate,
+    CancelOpposite,
+    DeadLoops,
+    ConstantFold,
+    PartialEval,
 }
 
-impl ParserMode {
+impl Pass {
+    pub const ALL: [Pass; 6] = [
+        Pass::StripComments,
+        Pass::Aggregate,
+        Pass::CancelOpposite,
+        Pass::DeadLoops,
+        Pass::ConstantFold,
+        Pass::PartialEval,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::StripComments => "strip-comments",
+            Pass::Aggregate => "aggregate",
+            Pass::CancelOpposite => "cancel-opposite",
+            Pass::DeadLoops => "dead-loops",
+            Pass::ConstantFold => "constant-fold",
+            Pass::PartialEval => "partial-eval",
+        }
+    }
 
-    pub(crate) fn keep_comments(&self) -> bool {
+    pub fn description(&self) -> &'static str {
         match self {
-            ParserMode::Debug => {false},
-            ParserMode::Release => {true},
+            Pass::StripComments => "Removes the comments, debug mode keeps them as tokens",
+            Pass::Aggregate => "Combines runs of the same operation like `+++`",
+            Pass::CancelOpposite => "Cancels opposite operations like `+-` and `<>`",
+            Pass::DeadLoops => "Removes loops on cells that are known to be zero",
+            Pass::ConstantFold => "Replaces clear loops like `[-]` on cells with known values",
+            Pass::PartialEval => "Executes the start of the program at compile time",
         }
     }
 
-    pub(crate) fn aggregate_instructions(&self) -> bool {
+    /// The lowest optimization level that enables the pass.
+    pub const fn level(&self) -> u8 {
+        match self {
+            Pass::StripComments | Pass::Aggregate => 1,
+            Pass::CancelOpposite | Pass::DeadLoops | Pass::ConstantFold => 2,
+            Pass::PartialEval => 3,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        Pass::ALL.iter().copied().find(|pass| pass.name() == name)
+    }
+
+    /// Creates the pass for a tape that wraps around after `wrap` cells.
+    pub(crate) fn create<T: Cell>(&self, wrap: Option<usize>) -> Box<dyn Postprocessor<T>> {
         match self {
-            ParserMode::Debug => {false},
-            ParserMode::Release => {true},
+            Pass::StripComments => Box::new(StripCommentsProcessor::create()),
+            Pass::Aggregate => Box::new(AggregateSameProcessor::create()),
+            Pass::CancelOpposite => Box::new(CancelOppositeProcessor::create()),
+            Pass::DeadLoops => Box::new(DeadLoopProcessor::create(wrap)),
+            Pass::ConstantFold => Box::new(ConstantFoldProcessor::create(wrap)),
+            Pass::PartialEval => Box::new(PartialEvalProcessor::create(PARTIAL_EVAL_STEPS, wrap)),
         }
     }
 
-}
\ No newline at end of file
+    const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl Display for Pass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The enabled optimizer passes.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ParserMode {
+    passes: u32,
+}
+
+impl ParserMode {
+    pub const MAX_LEVEL: u8 = 3;
+    /// No optimizations, the comments are kept.
+    pub const DEBUG: ParserMode = ParserMode::level(0);
+    /// The level of the command line without `-O`.
+    pub const DEFAULT: ParserMode = ParserMode::level(1);
+    /// Every pass is enabled.
+    pub const ALL: ParserMode = ParserMode::level(ParserMode::MAX_LEVEL);
+
+    /// Enables the passes of the optimization level `-O0` to `-O3`.
+    pub const fn level(level: u8) -> Self {
+        let mut passes = 0;
+        let mut index = 0;
+        while index < Pass::ALL.len() {
+            let pass = Pass::ALL[index];
+            if pass.level() <= level {
+                passes |= pass.bit();
+            }
+            index += 1;
+        }
+
+        ParserMode {
+            passes,
+        }
+    }
+
+    pub fn with_pass(mut self, pass: Pass, enabled: bool) -> Self {
+        if enabled {
+            self.passes |= pass.bit();
+        } else {
+            self.passes &= !pass.bit();
+        }
+        self
+    }
+
+    pub fn is_enabled(&self, pass: Pass) -> bool {
+        self.passes & pass.bit() != 0
+    }
+
+    /// Returns the enabled passes in the order they are executed.
+    pub fn passes(self) -> impl Iterator<Item=Pass> {
+        Pass::ALL.iter().copied().filter(move |pass| self.is_enabled(*pass))
+    }
+}
+
+impl Display for ParserMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let passes: Vec<&str> = self.passes().map(|pass| pass.name()).collect();
+        if passes.is_empty() {
+            write!(f, "no passes")
+        } else {
+            write!(f, "{}", passes.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(ParserMode::DEBUG.passes().count(), 0);
+        assert_eq!(ParserMode::level(1).passes().collect::<Vec<_>>(), vec![Pass::StripComments, Pass::Aggregate]);
+        assert_eq!(ParserMode::ALL.passes().count(), Pass::ALL.len());
+
+        let mode = ParserMode::level(2)
+            .with_pass(Pass::DeadLoops, false)
+            .with_pass(Pass::PartialEval, true);
+        assert!(!mode.is_enabled(Pass::DeadLoops));
+        assert!(mode.is_enabled(Pass::PartialEval));
+        assert_eq!(Pass::from_name("constant-fold"), Some(Pass::ConstantFold));
+        assert_eq!(Pass::from_name("fold"), None);
+    }
+}
@@ -1,6 +1,8 @@
 use super::Postprocessor;
-use crate::parser::Token;
-use crate::Cell;
+use crate::parser::{Span, Token};
+use crate::parser::analysis::State;
+use crate::parser::evaluator::Evaluator;
+use crate::{Cell, Instruction};
 
 #[derive(Debug)]
 pub(crate) struct AggregateSameProcessor;
@@ -33,9 +35,13 @@ impl<T> Postprocessor<T> for AggregateSameProcessor
             let item = item.unwrap();
 
             match item {
-                Token::LoopBlock(content) => {
+                Token::LoopBlock(content, span) => {
                     result.push(last_item);
-                    last_item = Token::LoopBlock(self.process(content));
+                    last_item = Token::LoopBlock(self.process(content), span);
+                },
+                Token::ProcedureBlock(content, span) => {
+                    result.push(last_item);
+                    last_item = Token::ProcedureBlock(self.process(content), span);
                 },
                 item => {
                     if last_item.can_join(&item) {
@@ -51,4 +57,297 @@ impl<T> Postprocessor<T> for AggregateSameProcessor
         result.push(last_item);
         result
     }
-}
\ No newline at end of file
+}
+
+/// Removes the comments. Only an explicit `_` is kept as a `NoOp`.
+#[derive(Debug)]
+pub(crate) struct StripCommentsProcessor;
+
+impl StripCommentsProcessor {
+    pub fn create() -> Self {
+        StripCommentsProcessor {
+
+        }
+    }
+}
+
+impl<T> Postprocessor<T> for StripCommentsProcessor
+    where T: Cell
+{
+    fn process(&self, code: Vec<Token<T>>) -> Vec<Token<T>> {
+        code.into_iter()
+            .filter_map(|token| match token {
+                Token::Comment(_, _) => None,
+                Token::LoopBlock(content, span) => Some(Token::LoopBlock(self.process(content), span)),
+                Token::ProcedureBlock(content, span) => Some(Token::ProcedureBlock(self.process(content), span)),
+                token => Some(token),
+            })
+            .collect()
+    }
+}
+
+/// Joins the same instructions and cancels opposite instructions like `+-`
+/// or `<>`. Instructions that become neighbours after a cancellation are
+/// combined as well.
+#[derive(Debug)]
+pub(crate) struct CancelOppositeProcessor;
+
+impl CancelOppositeProcessor {
+    pub fn create() -> Self {
+        CancelOppositeProcessor {
+
+        }
+    }
+}
+
+impl<T> Postprocessor<T> for CancelOppositeProcessor
+    where T: Cell
+{
+    fn process(&self, code: Vec<Token<T>>) -> Vec<Token<T>> {
+        let mut result: Vec<Token<T>> = Vec::new();
+
+        for token in code {
+            let token = match token {
+                Token::LoopBlock(content, span) => Token::LoopBlock(self.process(content), span),
+                Token::ProcedureBlock(content, span) => Token::ProcedureBlock(self.process(content), span),
+                token => token,
+            };
+
+            match (result.last(), &token) {
+                (Some(last @ Token::Instruction(..)), Token::Instruction(..)) if last.can_join(&token) => {
+                    let last = result.pop().unwrap();
+                    result.push(last.join(token));
+                },
+                (Some(Token::Instruction(last, _)), Token::Instruction(inst, _)) if last.is_opposite(inst) => {
+                    if let (Some(Token::Instruction(last, last_span)), Token::Instruction(inst, span)) = (result.pop(), token) {
+                        if let Some(inst) = last.cancel(inst) {
+                            result.push(Token::Instruction(inst, last_span.join(span)));
+                        }
+                    }
+                },
+                _ => result.push(token),
+            }
+        }
+
+        result
+    }
+}
+
+/// Removes loops that are never executed, because the current cell is
+/// known to be zero, like at the start of the program or after a loop.
+#[derive(Debug)]
+pub(crate) struct DeadLoopProcessor {
+    /// The size of the tape if it wraps around.
+    wrap: Option<usize>,
+}
+
+impl DeadLoopProcessor {
+    pub fn create(wrap: Option<usize>) -> Self {
+        DeadLoopProcessor {
+            wrap,
+        }
+    }
+
+    fn remove_dead_loops<T: Cell>(&self, code: Vec<Token<T>>, mut state: State<T>) -> Vec<Token<T>> {
+        let mut result = Vec::new();
+
+        for token in code {
+            if matches!(token, Token::LoopBlock(..)) && state.is_zero() {
+                continue;
+            }
+
+            let token = match token {
+                Token::LoopBlock(content, span) => {
+                    let inner = state.enter_loop(&content);
+                    Token::LoopBlock(self.remove_dead_loops(content, inner), span)
+                },
+                Token::ProcedureBlock(content, span) => {
+                    Token::ProcedureBlock(self.remove_dead_loops(content, State::unknown(self.wrap)), span)
+                },
+                token => token,
+            };
+            state.apply(&token);
+            result.push(token);
+        }
+
+        result
+    }
+}
+
+impl<T> Postprocessor<T> for DeadLoopProcessor
+    where T: Cell
+{
+    fn process(&self, code: Vec<Token<T>>) -> Vec<Token<T>> {
+        self.remove_dead_loops(code, State::start(self.wrap))
+    }
+}
+
+/// Replaces the clear loops `[-]` and `[+]` by a single instruction if the
+/// value of the cell is known.
+#[derive(Debug)]
+pub(crate) struct ConstantFoldProcessor {
+    /// The size of the tape if it wraps around.
+    wrap: Option<usize>,
+}
+
+impl ConstantFoldProcessor {
+    pub fn create(wrap: Option<usize>) -> Self {
+        ConstantFoldProcessor {
+            wrap,
+        }
+    }
+
+    fn fold<T: Cell>(&self, code: Vec<Token<T>>, mut state: State<T>) -> Vec<Token<T>> {
+        let mut result = Vec::new();
+
+        for token in code {
+            let token = match token {
+                Token::LoopBlock(content, span) => match (state.current(), content.as_slice()) {
+                    (Some(value), [Token::Instruction(Instruction::DecreaseValue(x), _)]) if *x == T::from(1) => {
+                        Token::Instruction(Instruction::DecreaseValue(value), span)
+                    },
+                    (Some(value), [Token::Instruction(Instruction::IncreaseValue(x), _)]) if *x == T::from(1) => {
+                        Token::Instruction(Instruction::IncreaseValue(T::default().sub_overflow(&value)), span)
+                    },
+                    _ => {
+                        let inner = state.enter_loop(&content);
+                        Token::LoopBlock(self.fold(content, inner), span)
+                    },
+                },
+                Token::ProcedureBlock(content, span) => {
+                    Token::ProcedureBlock(self.fold(content, State::unknown(self.wrap)), span)
+                },
+                token => token,
+            };
+
+            // A zero value folds to nothing
+            if let Token::Instruction(Instruction::IncreaseValue(x) | Instruction::DecreaseValue(x), _) = &token {
+                if *x == T::default() {
+                    continue;
+                }
+            }
+
+            state.apply(&token);
+            result.push(token);
+        }
+
+        result
+    }
+}
+
+impl<T> Postprocessor<T> for ConstantFoldProcessor
+    where T: Cell
+{
+    fn process(&self, code: Vec<Token<T>>) -> Vec<Token<T>> {
+        self.fold(code, State::start(self.wrap))
+    }
+}
+
+/// Executes the start of the program at compile time until an instruction
+/// depends on the input, files, banks or procedures. The executed tokens are
+/// replaced by the output they wrote and the code that sets the tape to the
+/// resulting state.
+#[derive(Debug)]
+pub(crate) struct PartialEvalProcessor {
+    /// The bound of the executed steps, loops might never terminate.
+    max_steps: usize,
+    /// The size of the tape if it wraps around.
+    wrap: Option<usize>,
+}
+
+impl PartialEvalProcessor {
+    pub fn create(max_steps: usize, wrap: Option<usize>) -> Self {
+        PartialEvalProcessor {
+            max_steps,
+            wrap,
+        }
+    }
+}
+
+impl<T> Postprocessor<T> for PartialEvalProcessor
+    where T: Cell
+{
+    fn process(&self, code: Vec<Token<T>>) -> Vec<Token<T>> {
+        let mut evaluator = Evaluator::new(self.max_steps, self.wrap);
+        let mut span: Option<Span> = None;
+        let mut done = 0;
+
+        for token in code.iter() {
+            // A loop can stop in the middle, the state is restored then
+            let result = match token {
+                Token::LoopBlock(..) => {
+                    let mut next = evaluator.clone();
+                    let result = next.run(std::slice::from_ref(token));
+                    if result.is_ok() {
+                        evaluator = next;
+                    }
+                    result
+                },
+                token => evaluator.run(std::slice::from_ref(token)),
+            };
+            if result.is_err() {
+                break;
+            }
+
+            span = Some(span.map_or(token.span(), |span| span.join(token.span())));
+            done += 1;
+        }
+
+        let Some(span) = span else {
+            return code;
+        };
+
+        let mut result = evaluator.emit(span);
+        result.extend(code.into_iter().skip(done));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Lexer, printer};
+
+    fn process<P: Postprocessor<u32>>(processor: P, code: &str) -> String {
+        let tokens = StripCommentsProcessor::create().process(Lexer::new().lex_string(code));
+        printer::print_tokens(&processor.process(tokens))
+    }
+
+    #[test]
+    fn test_cancel_opposite() {
+        assert_eq!(process(CancelOppositeProcessor::create(), "++-- >+-<"), "");
+        assert_eq!(process(CancelOppositeProcessor::create(), "+++-.<<>"), "++.<");
+        assert_eq!(process(CancelOppositeProcessor::create(), "[-+>-<+]_+-"), "[>-<+]_");
+    }
+
+    #[test]
+    fn test_dead_loops() {
+        assert_eq!(process(DeadLoopProcessor::create(None), "[-]_(+)[+]+[-][>][<]."), "_(+)+[-].");
+        assert_eq!(process(DeadLoopProcessor::create(None), "+[[-]]"), "+[[-]]");
+        // The known values of other cells are used
+        assert_eq!(process(DeadLoopProcessor::create(None), "+>[-]<[->+<]>[-]>[+]"), "+><[->+<]>[-]>");
+        // The pointer reaches the first cell again on a tape with 4 cells
+        assert_eq!(process(DeadLoopProcessor::create(Some(4)), "+>>>>[.[-]]"), "+>>>>[.[-]]");
+        assert_eq!(process(DeadLoopProcessor::create(Some(5)), "+>>>>[.[-]]"), "+>>>>");
+    }
+
+    #[test]
+    fn test_partial_eval() {
+        assert_eq!(process(PartialEvalProcessor::create(1000, None), "++[>+++<-]>.,[.,]"), "++++++.------>++++++,[.,]");
+        assert_eq!(process(PartialEvalProcessor::create(1000, None), ",+."), ",+.");
+        // The loop that reaches the bound is kept
+        assert_eq!(process(PartialEvalProcessor::create(1000, None), "+.>+[]"), "+.>+[]");
+        // The pointer reaches the first cell again on a tape with 4 cells
+        assert_eq!(process(PartialEvalProcessor::create(1000, Some(4)), "+>>>>+."), "+>>>>+.");
+        assert_eq!(process(PartialEvalProcessor::create(1000, Some(4)), "+>>>>[.[-]]"), "+>>>>[.[-]]");
+    }
+
+    #[test]
+    fn test_constant_fold() {
+        assert_eq!(process(ConstantFoldProcessor::create(None), "+++[-]>[+]"), "+++--->");
+        assert_eq!(process(ConstantFoldProcessor::create(None), "+>++<,[-]>[-]"), "+>++<,[-]>--");
+        assert_eq!(process(ConstantFoldProcessor::create(None), "[>+<-]>[-]"), "[>+<-]>");
+        // The pointer reaches the first cell again on a tape with 4 cells
+        assert_eq!(process(ConstantFoldProcessor::create(Some(4)), "+>>>>[-]"), "+>>>>[-]");
+    }
+}
@@ -0,0 +1,305 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const INCLUDE_DIRECTIVE: &str = "!include";
+/// The file name that is used for code that doesn't come from a file.
+const NO_FILE: &str = "<src>";
+
+/// A file and position in the original sources.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub file: &'a str,
+    /// The line number starting at 1.
+    pub line: usize,
+    /// The column in chars starting at 1.
+    pub column: usize,
+}
+
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// The origin of a line in the preprocessed code.
+#[derive(Debug, Clone, Copy)]
+struct LineOrigin {
+    /// The byte offset of the line in the preprocessed code.
+    start: usize,
+    file: usize,
+    line: usize,
+}
+
+/// Maps the offsets in the preprocessed code back to the included files.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<String>,
+    lines: Vec<LineOrigin>,
+}
+
+/// The code with all includes resolved.
+#[derive(Debug)]
+pub struct Source {
+    pub code: String,
+    pub map: SourceMap,
+}
+
+impl Source {
+    /// Returns the location of the byte `offset` in the preprocessed code.
+    /// This is used to map the span of a token to its file.
+    pub fn location(&self, offset: usize) -> Location<'_> {
+        let line = match self.map.lines.binary_search_by(|origin| origin.start.cmp(&offset)) {
+            Ok(line) => line,
+            Err(0) => return Location { file: NO_FILE, line: 1, column: 1 },
+            Err(line) => line - 1,
+        };
+
+        let origin = self.map.lines[line];
+        let offset = offset.min(self.code.len());
+        Location {
+            file: &self.map.files[origin.file],
+            line: origin.line,
+            column: self.code.get(origin.start..offset).map_or(1, |text| text.chars().count() + 1),
+        }
+    }
+
+    /// Replaces every line with the result of `f`. The lines keep their
+    /// origin, the columns of a changed line refer to the new text.
+    pub fn map_lines<E, F>(self, mut f: F) -> Result<Source, E>
+        where F: FnMut(&str, Location<'_>) -> Result<String, E>
+    {
+        let mut result = Source {
+            code: String::with_capacity(self.code.len()),
+            map: SourceMap {
+                files: Vec::new(),
+                lines: Vec::with_capacity(self.map.lines.len()),
+            },
+        };
+
+        for (index, origin) in self.map.lines.iter().enumerate() {
+            let end = self.map.lines.get(index + 1).map_or(self.code.len(), |next| next.start);
+            let location = Location {
+                file: &self.map.files[origin.file],
+                line: origin.line,
+                column: 1,
+            };
+            let line = f(&self.code[origin.start..end], location)?;
+
+            result.map.lines.push(LineOrigin {
+                start: result.code.len(),
+                ..*origin
+            });
+            result.code.push_str(&line);
+        }
+
+        result.map.files = self.map.files;
+        Ok(result)
+    }
+}
+
+#[derive(Debug)]
+pub enum IncludeError {
+    /// The directive at the location is malformed.
+    Syntax(String),
+    NotFound {
+        path: String,
+        location: String,
+    },
+    /// The chain of files that includes itself.
+    Cycle(Vec<String>),
+    Io {
+        path: String,
+        error: io::Error,
+    },
+}
+
+impl Display for IncludeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IncludeError::Syntax(location) => write!(
+                f, "{}: Expected an include like `{} \"lib/file.bd\"`", location, INCLUDE_DIRECTIVE),
+            IncludeError::NotFound { path, location } => write!(
+                f, "{}: The file \"{}\" was not found in the directory of the file or the library paths",
+                location, path),
+            IncludeError::Cycle(files) => write!(f, "The files include each other: {}", files.join(" -> ")),
+            IncludeError::Io { path, error } => write!(f, "Unable to read \"{}\": {}", path, error),
+        }
+    }
+}
+
+/// Resolves the `!include "file"` lines before the code is lexed. An include
+/// line is replaced by the content of the file. The files are searched in
+/// the directory of the including file first and then in the library paths.
+#[derive(Debug, Default)]
+pub struct Preprocessor {
+    lib_paths: Vec<PathBuf>,
+}
+
+impl Preprocessor {
+    pub fn new(lib_paths: Vec<PathBuf>) -> Self {
+        Preprocessor {
+            lib_paths,
+        }
+    }
+
+    /// Resolves the includes in `code`. `file` is the name of the file that
+    /// contained the code, if there is one.
+    pub fn process(&self, code: &str, file: Option<&str>) -> Result<Source, IncludeError> {
+        let mut source = Source {
+            code: String::with_capacity(code.len()),
+            map: SourceMap::default(),
+        };
+        let mut stack = Vec::new();
+
+        let dir = match file {
+            Some(file) => {
+                stack.push(fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file)));
+                Path::new(file).parent().map(Path::to_path_buf).unwrap_or_default()
+            },
+            None => PathBuf::new(),
+        };
+
+        source.map.files.push(file.unwrap_or(NO_FILE).to_string());
+        self.include(code, 0, &dir, &mut stack, &mut source)?;
+
+        Ok(source)
+    }
+
+    fn include(
+        &self,
+        code: &str,
+        file: usize,
+        dir: &Path,
+        stack: &mut Vec<PathBuf>,
+        source: &mut Source) -> Result<(), IncludeError>
+    {
+        for (line_index, line) in code.split_inclusive('\n').enumerate() {
+            let directive = line.trim_start();
+            if !is_include(directive) {
+                source.map.lines.push(LineOrigin {
+                    start: source.code.len(),
+                    file,
+                    line: line_index + 1,
+                });
+                source.code.push_str(line);
+                continue;
+            }
+
+            let location = format!("{}:{}", source.map.files[file], line_index + 1);
+            let path = parse_include(&directive[INCLUDE_DIRECTIVE.len()..])
+                .ok_or_else(|| IncludeError::Syntax(location.clone()))?;
+            let resolved = self.resolve(path, dir)
+                .ok_or_else(|| IncludeError::NotFound {
+                    path: path.to_string(),
+                    location,
+                })?;
+
+            let canonical = fs::canonicalize(&resolved)
+                .map_err(|error| IncludeError::Io { path: resolved.display().to_string(), error })?;
+            if let Some(first) = stack.iter().position(|included| *included == canonical) {
+                let mut files: Vec<String> = stack[first..].iter()
+                    .map(|included| included.display().to_string())
+                    .collect();
+                files.push(canonical.display().to_string());
+                return Err(IncludeError::Cycle(files));
+            }
+
+            let content = fs::read_to_string(&resolved)
+                .map_err(|error| IncludeError::Io { path: resolved.display().to_string(), error })?;
+
+            source.map.files.push(resolved.display().to_string());
+            let included = source.map.files.len() - 1;
+            let included_dir = resolved.parent().map(Path::to_path_buf).unwrap_or_default();
+
+            stack.push(canonical);
+            self.include(&content, included, &included_dir, stack, source)?;
+            stack.pop();
+
+            // The next line has to start on a new line for the source map
+            if !source.code.is_empty() && !source.code.ends_with('\n') {
+                source.code.push('\n');
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Searches the file in `dir` and then in the library paths.
+    fn resolve(&self, path: &str, dir: &Path) -> Option<PathBuf> {
+        std::iter::once(dir)
+            .chain(self.lib_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+/// Returns `true` if the line is an include directive.
+pub fn is_include(line: &str) -> bool {
+    line.trim_start().starts_with(INCLUDE_DIRECTIVE)
+}
+
+/// Parses the `"path"` after the directive. Nothing else can follow the path.
+fn parse_include(text: &str) -> Option<&str> {
+    let text = text.trim().strip_prefix('"')?;
+    let (path, rest) = text.split_once('"')?;
+    if path.is_empty() || !rest.trim().is_empty() {
+        return None;
+    }
+
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    const TEST_DIR: &str = "preprocessor_test";
+
+    #[test]
+    fn test_parse_include() {
+        assert_eq!(parse_include(" \"lib/print.bd\"\n"), Some("lib/print.bd"));
+        assert_eq!(parse_include(" \"lib/print.bd\" +"), None);
+        assert_eq!(parse_include(" lib/print.bd"), None);
+        assert_eq!(parse_include(" \"\""), None);
+    }
+
+    #[test]
+    fn test_include() {
+        let _ = remove_dir_all(TEST_DIR);
+        create_dir_all(format!("{}/src", TEST_DIR)).unwrap();
+        create_dir_all(format!("{}/lib", TEST_DIR)).unwrap();
+        write(format!("{}/lib/print.bd", TEST_DIR), "print\n.").unwrap();
+        write(format!("{}/src/inc.bd", TEST_DIR), "+\n  !include \"print.bd\"\n-").unwrap();
+        write(format!("{}/src/cycle.bd", TEST_DIR), "!include \"cycle2.bd\"").unwrap();
+        write(format!("{}/src/cycle2.bd", TEST_DIR), "!include \"cycle.bd\"").unwrap();
+
+        let main_file = format!("{}/main.bd", TEST_DIR);
+        let main = "++ main\n!include \"src/inc.bd\"\n>>";
+        let processor = Preprocessor::new(vec![PathBuf::from(format!("{}/lib", TEST_DIR))]);
+        let source = processor.process(main, Some(&main_file)).unwrap();
+
+        assert_eq!(source.code, "++ main\n+\nprint\n.\n-\n>>");
+        let location = |offset| {
+            let location = source.location(offset);
+            (location.file.replace('\\', "/"), location.line, location.column)
+        };
+        assert_eq!(location(1), (main_file.clone(), 1, 2));
+        assert_eq!(location(8), (format!("{}/src/inc.bd", TEST_DIR), 1, 1));
+        assert_eq!(location(16), (format!("{}/lib/print.bd", TEST_DIR), 2, 1));
+        assert_eq!(location(18), (format!("{}/src/inc.bd", TEST_DIR), 3, 1));
+        assert_eq!(location(20), (main_file.clone(), 3, 1));
+
+        // Missing files and cycles are errors
+        assert!(matches!(processor.process("!include \"missing.bd\"", None), Err(IncludeError::NotFound { .. })));
+        assert!(matches!(processor.process("!include  lib", None), Err(IncludeError::Syntax(_))));
+        match processor.process("!include \"src/cycle.bd\"", Some(&main_file)) {
+            Err(IncludeError::Cycle(files)) => assert_eq!(files.len(), 3),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        remove_dir_all(TEST_DIR).unwrap();
+    }
+}
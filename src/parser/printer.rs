@@ -0,0 +1,185 @@
+use crate::{Cell, Instruction};
+use crate::parser::Token;
+use crate::parser::lexer::*;
+use std::fmt::Write;
+
+/// The formats of `--emit` and `--dump-after`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmitFormat {
+    /// The token tree with the spans in the source.
+    Tokens,
+    /// The flattened instructions with the jump targets.
+    Ir,
+    /// The flattened instructions like the interpreter executes them.
+    Bytecode,
+}
+
+impl EmitFormat {
+    pub fn from_name(name: &str) -> Option<EmitFormat> {
+        match name {
+            "tokens" => Some(EmitFormat::Tokens),
+            "ir" => Some(EmitFormat::Ir),
+            "bytecode" => Some(EmitFormat::Bytecode),
+            _ => None,
+        }
+    }
+}
+
+/// Prints the tokens in the format.
+pub(crate) fn emit<T: Cell>(tokens: &[Token<T>], format: EmitFormat) -> String {
+    match format {
+        EmitFormat::Tokens => {
+            let mut text = String::new();
+            write_tree(&mut text, tokens, 0);
+            text
+        },
+        EmitFormat::Ir => print_ir(&Lexer::new().flatten(tokens.to_vec())),
+        EmitFormat::Bytecode => Lexer::new().flatten(tokens.to_vec())
+            .iter()
+            .enumerate()
+            .map(|(index, inst)| format!("{:04}  {:?}\n", index, inst))
+            .collect(),
+    }
+}
+
+fn write_tree<T: Cell>(text: &mut String, tokens: &[Token<T>], depth: usize) {
+    for token in tokens {
+        let span = token.span();
+        let _ = write!(text, "{:>6}..{:<6} {}", span.start, span.end, "  ".repeat(depth));
+        match token {
+            Token::Instruction(inst, _) => {
+                let _ = writeln!(text, "{:?}", inst);
+            },
+            Token::LoopBlock(content, _) => {
+                text.push_str("Loop\n");
+                write_tree(text, content, depth + 1);
+            },
+            Token::ProcedureBlock(content, _) => {
+                text.push_str("Procedure\n");
+                write_tree(text, content, depth + 1);
+            },
+            Token::Comment(comment, _) => {
+                let _ = writeln!(text, "Comment({:?})", comment);
+            },
+        }
+    }
+}
+
+/// Prints one instruction per line. Jumps show the position that is executed
+/// next if the jump is taken.
+pub(crate) fn print_ir<T: Cell>(code: &[Instruction<T>]) -> String {
+    let mut text = String::new();
+
+    for (index, inst) in code.iter().enumerate() {
+        let (name, operand, target) = match inst {
+            Instruction::NoOp => ("nop", None, None),
+            Instruction::IncreaseValue(x) => ("add", Some(x.to_usize()), None),
+            Instruction::DecreaseValue(x) => ("sub", Some(x.to_usize()), None),
+            Instruction::IncreaseIndex(x) => ("right", Some(*x), None),
+            Instruction::DecreaseIndex(x) => ("left", Some(*x), None),
+            Instruction::IoRead => ("in", None, None),
+            Instruction::IoWrite => ("out", None, None),
+            Instruction::LoopStart(x) => ("loop", None, Some(index.checked_add(*x).and_then(|end| end.checked_add(1)))),
+            Instruction::LoopEnd(x) => ("end", None, Some((index + 1).checked_sub(*x))),
+            Instruction::FileIoRead => ("fread", None, None),
+            Instruction::FileIoWrite => ("fwrite", None, None),
+            Instruction::FileSelect => ("fselect", None, None),
+            Instruction::FileIoAppend => ("fappend", None, None),
+            Instruction::FileSeek => ("fseek", None, None),
+            Instruction::BankSwitch => ("bank", None, None),
+            Instruction::ProcedureStart(x) => ("proc", None, Some(index.checked_add(*x).and_then(|end| end.checked_add(1)))),
+            Instruction::ProcedureEnd => ("ret", None, None),
+            Instruction::CallProcedure => ("call", None, None),
+        };
+
+        let _ = write!(text, "{:04}  {}", index, name);
+        if let Some(operand) = operand {
+            let _ = write!(text, " {}", operand);
+        }
+        match target {
+            Some(Some(target)) => { let _ = write!(text, " -> {:04}", target); },
+            Some(None) => text.push_str(" -> ?"),
+            None => {},
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Prints the tokens as Braindamage source. Comments are printed as they are.
+pub(crate) fn print_tokens<T: Cell>(tokens: &[Token<T>]) -> String {
+    let mut source = String::new();
+    write_tokens(&mut source, tokens);
+    source
+}
+
+fn write_tokens<T: Cell>(source: &mut String, tokens: &[Token<T>]) {
+    for token in tokens {
+        match token {
+            Token::Instruction(inst, _) => write_instruction(source, inst),
+            Token::LoopBlock(content, _) => {
+                source.push(OP_LOOP_START);
+                write_tokens(source, content);
+                source.push(OP_LOOP_END);
+            },
+            Token::ProcedureBlock(content, _) => {
+                source.push(OP_PROCEDURE_START);
+                write_tokens(source, content);
+                source.push(OP_PROCEDURE_END);
+            },
+            Token::Comment(text, _) => source.push_str(text),
+        }
+    }
+}
+
+fn write_instruction<T: Cell>(source: &mut String, inst: &Instruction<T>) {
+    let (op, count) = match inst {
+        Instruction::NoOp => (OP_NOOP, 1),
+        Instruction::IncreaseIndex(x) => (OP_INC_INDEX, *x),
+        Instruction::DecreaseIndex(x) => (OP_DEC_INDEX, *x),
+        Instruction::IncreaseValue(x) => (OP_INC_VALUE, x.to_usize()),
+        Instruction::DecreaseValue(x) => (OP_DEC_VALUE, x.to_usize()),
+        Instruction::IoRead => (OP_IO_READ, 1),
+        Instruction::IoWrite => (OP_IO_WRITE, 1),
+        // Blocks are tokens, these only exist in flattened code
+        Instruction::LoopStart(_) => (OP_LOOP_START, 1),
+        Instruction::LoopEnd(_) => (OP_LOOP_END, 1),
+        Instruction::FileIoRead => (OP_FILE_IO_READ, 1),
+        Instruction::FileIoWrite => (OP_FILE_IO_WRITE, 1),
+        Instruction::FileSelect => (OP_FILE_SELECT, 1),
+        Instruction::FileIoAppend => (OP_FILE_IO_APPEND, 1),
+        Instruction::FileSeek => (OP_FILE_SEEK, 1),
+        Instruction::BankSwitch => (OP_BANK_SWITCH, 1),
+        Instruction::ProcedureStart(_) => (OP_PROCEDURE_START, 1),
+        Instruction::ProcedureEnd => (OP_PROCEDURE_END, 1),
+        Instruction::CallProcedure => (OP_CALL_PROCEDURE, 1),
+    };
+
+    source.extend(std::iter::repeat_n(op, count));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Lexer;
+
+    #[test]
+    fn test_emit() {
+        let tokens: Vec<Token<u8>> = Lexer::new().lex_string("+[-]x");
+        assert_eq!(emit(&tokens, EmitFormat::Tokens), concat!(
+            "     0..1      IncreaseValue(1)\n",
+            "     1..4      Loop\n",
+            "     2..3        DecreaseValue(1)\n",
+            "     4..5      Comment(\"x\")\n"));
+        assert_eq!(emit(&tokens, EmitFormat::Ir), "0000  add 1\n0001  loop -> 0004\n0002  sub 1\n0003  end -> 0002\n");
+        assert_eq!(emit(&tokens, EmitFormat::Bytecode), "0000  IncreaseValue(1)\n0001  LoopStart(2)\n0002  DecreaseValue(1)\n0003  LoopEnd(2)\n");
+    }
+
+    #[test]
+    fn test_print_tokens() {
+        let code = "+[->+<]> text (%)|#&=:;,._";
+        let tokens: Vec<Token<u8>> = Lexer::new().lex_string(code);
+        assert_eq!(print_tokens(&tokens), code);
+    }
+}
@@ -1,14 +1,48 @@
 use crate::{Cell, Instruction};
 
+/// The byte range of a token in the preprocessed source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span {
+            start,
+            end,
+        }
+    }
+
+    /// Returns the span that covers both spans.
+    pub fn join(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Token<T: Cell> {
-    Instruction(Instruction<T>),
-    LoopBlock(Vec<Token<T>>)
+    Instruction(Instruction<T>, Span),
+    LoopBlock(Vec<Token<T>>, Span),
+    ProcedureBlock(Vec<Token<T>>, Span),
+    /// The text between instructions.
+    Comment(String, Span),
 }
 
 impl<T: Cell> Token<T> {
+    pub fn span(&self) -> Span {
+        match self {
+            Token::Instruction(_, span) => *span,
+            Token::LoopBlock(_, span) => *span,
+            Token::ProcedureBlock(_, span) => *span,
+            Token::Comment(_, span) => *span,
+        }
+    }
+
     pub fn can_join(&self, other: &Token<T>) -> bool {
         match (self, other) {
-            (Token::Instruction(x), Token::Instruction(y))
+            (Token::Instruction(x, _), Token::Instruction(y, _))
             => x.can_join(y),
             _ => false
         }
@@ -16,9 +50,9 @@ impl<T: Cell> Token<T> {
 
     pub fn join(self, other: Token<T>) -> Token<T> {
         match (self, other) {
-            (Token::Instruction(x), Token::Instruction(y))
-            => Token::Instruction(x.join(y)),
+            (Token::Instruction(x, a), Token::Instruction(y, b))
+            => Token::Instruction(x.join(y), a.join(b)),
             _ => panic!("No Join here")
         }
     }
-}
\ No newline at end of file
+}
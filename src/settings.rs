@@ -1,15 +1,59 @@
 use std::fs::File;
 use std::io::Read;
 use std::process;
-use crate::parser::{ParserMode};
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::parser::{EmitFormat, ParserMode, Pass};
+use crate::operations::io::file_io::FileAccess;
+use crate::interpreter::Limits;
+use crate::dump::{DumpSettings, DumpFormat, DumpRange};
+use crate::buffer::TapeKind;
+
+const DEFAULT_DATA_FILE: &str = "bd_data.txt";
+const DEFAULT_MAX_DEPTH: usize = 10_000;
 
 enum ArgState {
     SkipExeFile,
+    CommandArg,
     Start,
     FileArg,
     SrcArg,
     CellArg,
-    BufferArg
+    BufferArg,
+    TapeArg,
+    BanksArg,
+    InputArg,
+    InputStrArg,
+    OutputArg,
+    DataFileArg,
+    LibPathArg,
+    PassArg,
+    EmitArg,
+    DumpAfterArg,
+    SandboxArg,
+    MaxStepsArg,
+    TimeoutArg,
+    MaxMemoryArg,
+    MaxDepthArg,
+    ResumeArg,
+    SnapshotArg,
+    DumpRangeArg,
+    DumpAroundPointerArg,
+    DumpFormatArg,
+    DumpFileArg,
+    WidthArg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Runs the program.
+    Run,
+    /// Formats the source file.
+    Fmt,
+    /// Prints the smallest equivalent source.
+    Minify,
+    /// Prints warnings for common mistakes.
+    Lint,
 }
 
 #[derive(Debug, PartialEq)]
@@ -19,13 +63,58 @@ pub enum CellType {
     U32,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum InputSource {
+    Console,
+    File(String),
+    Str(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OutputTarget {
+    Console,
+    File(String),
+}
+
 #[derive(Debug)]
 pub struct Settings {
+    pub command: Command,
+    /// The file for the command, like `fmt <file>`.
+    pub tool_file: Option<String>,
+    /// The command only checks the file without changing it.
+    pub check: bool,
+    /// The line width of the formatted code.
+    pub width: Option<usize>,
     pub src: String,
+    /// The file of the src, if it was loaded from a file.
+    pub src_file: Option<String>,
+    /// The directories that are searched for `!include` files.
+    pub lib_paths: Vec<PathBuf>,
+    /// Expands macros like `+*16` before the code is parsed.
+    pub macros: bool,
     pub parser_mode: ParserMode,
+    /// Lists the optimizer passes instead of running the program.
+    pub print_passes: bool,
+    /// Prints the optimized code instead of running the program.
+    pub emit: Option<EmitFormat>,
+    /// Prints the code to stderr after the pass.
+    pub dump_after: Option<Pass>,
     pub dump_mem: bool,
+    pub dump: DumpSettings,
+    pub verbose: bool,
+    pub print_stats: bool,
     pub cell_type: CellType,
-    pub buffer_size: usize
+    pub buffer_size: usize,
+    pub tape_kind: TapeKind,
+    /// The number of memory banks, every bank has its own buffer.
+    pub banks: usize,
+    pub input: InputSource,
+    pub output: OutputTarget,
+    pub data_files: Vec<String>,
+    pub file_access: FileAccess,
+    pub limits: Limits,
+    pub resume_file: Option<String>,
+    pub snapshot_file: Option<String>
 }
 
 impl Settings {
@@ -34,12 +123,25 @@ impl Settings {
     {
         let mut settings = Self::init_default();
 
+        // The passes are changed after the optimization level was set
+        let mut passes = Vec::new();
+
         let mut mode = ArgState::SkipExeFile;
         for arg in args {
             match mode {
-                ArgState::SkipExeFile => mode = ArgState::Start,
-                ArgState::Start => {
+                ArgState::SkipExeFile => mode = ArgState::CommandArg,
+                ArgState::CommandArg | ArgState::Start => {
+                    // The command has to be the first argument
+                    let first = matches!(mode, ArgState::CommandArg);
+                    mode = ArgState::Start;
+
                     match arg.as_str() {
+                        "fmt" if first => settings.command = Command::Fmt,
+                        "minify" if first => settings.command = Command::Minify,
+                        "lint" if first => settings.command = Command::Lint,
+                        "--check" if settings.command != Command::Run => settings.check = true,
+                        "--width" if settings.command != Command::Run => mode = ArgState::WidthArg,
+
                         "-f" | "--file" => mode = ArgState::FileArg,
                         "-s" | "--src" => mode = ArgState::SrcArg,
                         "-h" | "--help" => {
@@ -47,11 +149,50 @@ impl Settings {
                             process::exit(0);
                         }
 
-                        "-d" | "--debug" => settings.parser_mode = ParserMode::Debug,
+                        "-d" | "--debug" => settings.parser_mode = ParserMode::DEBUG,
+                        "--pass" => mode = ArgState::PassArg,
+                        "--print-passes" => settings.print_passes = true,
+                        "--emit" => mode = ArgState::EmitArg,
+                        "--dump-after" => mode = ArgState::DumpAfterArg,
+                        x if x.starts_with("-O") => {
+                            match x[2..].parse::<u8>() {
+                                Ok(level) if level <= ParserMode::MAX_LEVEL => settings.parser_mode = ParserMode::level(level),
+                                _ => println!("Please enter a valid optimization level. Valid levels are: -O0 to -O{}.",
+                                              ParserMode::MAX_LEVEL),
+                            }
+                        },
                         "--dump" => settings.dump_mem = true,
+                        "--dump-range" => mode = ArgState::DumpRangeArg,
+                        "--dump-around-pointer" => mode = ArgState::DumpAroundPointerArg,
+                        "--dump-format" => mode = ArgState::DumpFormatArg,
+                        "--dump-file" => mode = ArgState::DumpFileArg,
+                        "-v" | "--verbose" => settings.verbose = true,
+                        "--stats" => settings.print_stats = true,
 
                         "-c" | "--cell" => mode = ArgState::CellArg,
                         "-b" | "--buffer" => mode = ArgState::BufferArg,
+                        "-t" | "--tape" => mode = ArgState::TapeArg,
+                        "--banks" => mode = ArgState::BanksArg,
+
+                        "--input" => mode = ArgState::InputArg,
+                        "--input-str" => mode = ArgState::InputStrArg,
+                        "--output" => mode = ArgState::OutputArg,
+                        "--data-file" => mode = ArgState::DataFileArg,
+                        "--lib-path" => mode = ArgState::LibPathArg,
+                        "--macros" => settings.macros = true,
+                        "--sandbox" => mode = ArgState::SandboxArg,
+                        "--no-file-io" => settings.file_access = FileAccess::Disabled,
+
+                        "--max-steps" => mode = ArgState::MaxStepsArg,
+                        "--timeout" => mode = ArgState::TimeoutArg,
+                        "--max-memory" => mode = ArgState::MaxMemoryArg,
+                        "--max-depth" => mode = ArgState::MaxDepthArg,
+
+                        "--resume" => mode = ArgState::ResumeArg,
+                        "--snapshot" => mode = ArgState::SnapshotArg,
+                        x if settings.command != Command::Run && settings.tool_file.is_none() && !x.starts_with('-') => {
+                            settings.tool_file = Some(x.to_string());
+                        },
                         x => {
                             println!("Unknown argument: {}", x);
                             print_help();
@@ -60,12 +201,13 @@ impl Settings {
                     }
                 },
                 ArgState::FileArg => {
-                    let src = load_source_file(arg);
+                    let src = load_source_file(arg.clone());
                     if src.is_none() {
                         process::exit(-1);
                     }
 
                     settings.src = src.unwrap();
+                    settings.src_file = Some(arg);
                     mode = ArgState::Start;
                 },
                 ArgState::SrcArg => {
@@ -83,34 +225,249 @@ impl Settings {
                     mode = ArgState::Start;
                 },
                 ArgState::BufferArg => {
-                    let size = arg.to_string().parse::<usize>();
-                    if size.is_err() {
-                        println!("Please enter a valid buffer size.");
-                    } else {
-                        settings.buffer_size = size.unwrap();
+                    match parse_size(&arg) {
+                        Some(size) if size > 0 => settings.buffer_size = size,
+                        _ => println!("Please enter a valid buffer size."),
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::TapeArg => {
+                    match arg.as_str() {
+                        "vec" => settings.tape_kind = TapeKind::Vec,
+                        "paged" => settings.tape_kind = TapeKind::Paged,
+                        "infinite" => settings.tape_kind = TapeKind::Infinite,
+                        _ => println!("Please enter a valid tape. Valid tapes are: vec, paged, infinite.")
                     }
 
                     mode = ArgState::Start;
                 },
+                ArgState::BanksArg => {
+                    match arg.parse::<usize>() {
+                        Ok(banks) if banks > 0 => settings.banks = banks,
+                        _ => println!("Please enter a valid number of banks."),
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::InputArg => {
+                    settings.input = InputSource::File(arg);
+                    mode = ArgState::Start;
+                },
+                ArgState::InputStrArg => {
+                    settings.input = InputSource::Str(arg);
+                    mode = ArgState::Start;
+                },
+                ArgState::OutputArg => {
+                    settings.output = OutputTarget::File(arg);
+                    mode = ArgState::Start;
+                },
+                ArgState::WidthArg => {
+                    match arg.parse::<usize>() {
+                        Ok(width) if width > 0 => settings.width = Some(width),
+                        _ => println!("Please enter a valid line width."),
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::PassArg => {
+                    let change = match arg.split_at_checked(1) {
+                        Some(("+", name)) => Pass::from_name(name).map(|pass| (pass, true)),
+                        Some(("-", name)) => Pass::from_name(name).map(|pass| (pass, false)),
+                        _ => None,
+                    };
+                    match change {
+                        Some(change) => passes.push(change),
+                        None => println!("Please enter a pass like +name or -name. Use --print-passes to list the passes."),
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::EmitArg => {
+                    match EmitFormat::from_name(&arg) {
+                        Some(format) => settings.emit = Some(format),
+                        None => println!("Please enter a valid emit format. Valid formats are: tokens, ir, bytecode."),
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::DumpAfterArg => {
+                    match Pass::from_name(&arg) {
+                        Some(pass) => settings.dump_after = Some(pass),
+                        None => println!("Please enter a valid pass. Use --print-passes to list the passes."),
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::LibPathArg => {
+                    settings.lib_paths.push(PathBuf::from(arg));
+                    mode = ArgState::Start;
+                },
+                ArgState::DataFileArg => {
+                    settings.data_files.push(arg);
+                    mode = ArgState::Start;
+                },
+                ArgState::SandboxArg => {
+                    // `--no-file-io` is never turned back on, the order of the arguments doesn't matter
+                    if settings.file_access != FileAccess::Disabled {
+                        settings.file_access = FileAccess::Sandbox(PathBuf::from(arg));
+                    }
+                    mode = ArgState::Start;
+                },
+                ArgState::MaxStepsArg => {
+                    match arg.parse::<u64>() {
+                        Ok(steps) => settings.limits.max_steps = Some(steps),
+                        Err(_) => {
+                            // A limit is never ignored, the program could be untrusted
+                            println!("Please enter a valid step limit.");
+                            process::exit(-1);
+                        },
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::TimeoutArg => {
+                    match arg.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()) {
+                        Some(timeout) => settings.limits.timeout = Some(timeout),
+                        None => {
+                            println!("Please enter a valid timeout in seconds.");
+                            process::exit(-1);
+                        },
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::MaxDepthArg => {
+                    match arg.parse::<usize>() {
+                        Ok(depth) => settings.limits.max_depth = Some(depth),
+                        Err(_) => {
+                            println!("Please enter a valid call depth.");
+                            process::exit(-1);
+                        },
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::MaxMemoryArg => {
+                    match parse_size(&arg) {
+                        Some(size) => settings.limits.max_memory = Some(size),
+                        None => {
+                            println!("Please enter a valid memory limit.");
+                            process::exit(-1);
+                        },
+                    }
+
+                    mode = ArgState::Start;
+                },
+                ArgState::ResumeArg => {
+                    settings.resume_file = Some(arg);
+                    mode = ArgState::Start;
+                },
+                ArgState::SnapshotArg => {
+                    settings.snapshot_file = Some(arg);
+                    mode = ArgState::Start;
+                },
+                ArgState::DumpRangeArg => {
+                    let range = arg.split_once("..")
+                        .and_then(|(start, end)| Some((start.parse::<isize>().ok()?, end.parse::<isize>().ok()?)));
+                    match range {
+                        Some((start, end)) => settings.dump.range = DumpRange::Range(start, end),
+                        None => println!("Please enter a valid dump range like 0..128."),
+                    }
+
+                    settings.dump_mem = true;
+                    mode = ArgState::Start;
+                },
+                ArgState::DumpAroundPointerArg => {
+                    match arg.parse::<usize>() {
+                        Ok(n) => settings.dump.range = DumpRange::AroundPointer(n),
+                        Err(_) => println!("Please enter a valid number of cells."),
+                    }
+
+                    settings.dump_mem = true;
+                    mode = ArgState::Start;
+                },
+                ArgState::DumpFormatArg => {
+                    match arg.as_str() {
+                        "hex" => settings.dump.format = DumpFormat::Hex,
+                        "dec" => settings.dump.format = DumpFormat::Dec,
+                        "ascii" => settings.dump.format = DumpFormat::Ascii,
+                        "json" => settings.dump.format = DumpFormat::Json,
+                        "raw" => settings.dump.format = DumpFormat::Raw,
+                        _ => println!("Please enter a valid dump format. Valid formats are: hex, dec, ascii, json, raw.")
+                    }
+
+                    settings.dump_mem = true;
+                    mode = ArgState::Start;
+                },
+                ArgState::DumpFileArg => {
+                    settings.dump.file = Some(arg);
+                    settings.dump_mem = true;
+                    mode = ArgState::Start;
+                },
             }
         }
 
-        return settings;
+        if matches!(mode, ArgState::MaxStepsArg | ArgState::TimeoutArg | ArgState::MaxMemoryArg | ArgState::MaxDepthArg) {
+            println!("Please enter a value for the limit.");
+            process::exit(-1);
+        }
+
+        for (pass, enabled) in passes {
+            settings.parser_mode = settings.parser_mode.with_pass(pass, enabled);
+        }
+
+        settings
     }
 
     fn init_default() -> Settings {
         Settings {
+            command: Command::Run,
+            tool_file: None,
+            check: false,
+            width: None,
             src: String::default(),
-            parser_mode: ParserMode::Release,
+            src_file: None,
+            lib_paths: Vec::new(),
+            macros: false,
+            parser_mode: ParserMode::DEFAULT,
+            print_passes: false,
+            emit: None,
+            dump_after: None,
             dump_mem: false,
+            dump: DumpSettings::default(),
+            verbose: false,
+            print_stats: false,
             cell_type: CellType::U8,
-            buffer_size: 30_000
+            buffer_size: 30_000,
+            tape_kind: TapeKind::Vec,
+            banks: 1,
+            input: InputSource::Console,
+            output: OutputTarget::Console,
+            data_files: Vec::new(),
+            file_access: FileAccess::Unrestricted,
+            limits: Limits {
+                max_depth: Some(DEFAULT_MAX_DEPTH),
+                ..Limits::default()
+            },
+            resume_file: None,
+            snapshot_file: None
         }
     }
 
     pub fn get_src(&self) -> String {
         self.src.clone()
     }
+
+    /// Returns the file table for `:` and `;`. The default file is used if
+    /// no `--data-file` was given.
+    pub fn get_data_files(&self) -> Vec<String> {
+        if self.data_files.is_empty() {
+            vec![DEFAULT_DATA_FILE.to_string()]
+        } else {
+            self.data_files.clone()
+        }
+    }
 }
 
 fn load_source_file(file_name: String) -> Option<String> {
@@ -128,25 +485,137 @@ fn load_source_file(file_name: String) -> Option<String> {
         return None;
     }
 
-    return Some(src);
+    Some(src)
+}
+
+/// Parses a size like `512`, `64K`, `16M` or `4G`. The suffixes are powers of 1024.
+fn parse_size(arg: &str) -> Option<usize> {
+    let (number, factor) = match arg.chars().last()?.to_ascii_uppercase() {
+        'K' => (&arg[..arg.len() - 1], 1usize << 10),
+        'M' => (&arg[..arg.len() - 1], 1usize << 20),
+        'G' => (&arg[..arg.len() - 1], 1usize << 30),
+        _ => (arg, 1),
+    };
+
+    number.parse::<usize>().ok()?.checked_mul(factor)
 }
 
 fn print_help() {
     println!("
 braindamage [[--help | -h] | [--file | -f <src-file>] | [--src | -s <src-code>]]
-            [-d | --debug] [--dump]
+            [-d | --debug] [-O<level>] [--pass (+|-)<name>] [--print-passes]
+            [--emit <format>] [--dump-after <pass>]
+            [--dump] [-v | --verbose] [--stats]
+            [--input <file> | --input-str <text>] [--output <file>]
+braindamage fmt <file> [--check] [--width <n>]
+braindamage minify <file> [--lib-path <dir>] [--macros]
+braindamage lint <file> [--lib-path <dir>] [--macros] [-b <size>] [-t <tape>]
+
+Commands:
+    fmt <file>                     Formats the file in place. Loops are indented by their depth,
+                                   comments are kept.
+       --check                     Only checks the file, the exit code is 1 if the file is not
+                                   formatted.
+       --width <n>                 Wraps the lines that are longer than n chars.
+    minify <file>                  Prints the smallest equivalent source. Comments are removed,
+                                   `+-` and `<>` cancel each other and dead loops are dropped.
+                                   Includes are resolved, macros are expanded with --macros.
+    lint <file>                    Prints warnings for common mistakes like loops that never
+                                   terminate or `;` reads without an end check. The exit code is
+                                   1 if there are warnings. -b and -t set the tape of the program.
 
 Main operation (Required):
     -s --src  <src-code>           Sets the provided string as the src.
     -f --file <src-file>           Sets the file content as the src.
+       --lib-path <dir>            Adds a directory that is searched for `!include \"file\"` lines.
+                                   Included files are first searched next to the including file.
+                                   This can be used multiple times.
+       --macros                    Expands macros before the code is parsed. `+*16` repeats the
+                                   `+` 16 times. A line `$name = ...;` defines a macro that is
+                                   inserted by `$name`.
     -h --help                      Prints this help message.
 
 Debugging:
     -d --debug                  This enabled debugging instructions and disables parser optimisation.
+                                The same as -O0.
        --dump                   Dumps out the memory buffer after execution.
+       --dump-range <a..b>      Dumps the cells from a to b. (Default: 0..128)
+       --dump-around-pointer <n>
+                                Dumps the cells that are at most n cells away from the pointer.
+       --dump-format (hex | dec | ascii | json | raw)
+                                Sets the dump format. The current cell is marked with `>`.
+                                The raw format writes the cell bytes and requires a dump file.
+                                (Default: hex)
+       --dump-file <file>       Writes the dump into the file instead of the console.
+    -v --verbose                Prints warnings for failed file operations to stderr.
+       --stats                  Prints execution statistics to stderr.
+
+Optimization:
+    -O0 | -O1 | -O2 | -O3          Sets the optimization level. -O1 removes comments and combines
+                                   runs like `+++`, -O2 uses the known cell values and -O3
+                                   executes the start of the program at compile time.
+                                   (Default: -O1)
+       --pass (+|-)<name>          Enables or disables a single pass after the level was applied.
+                                   This can be used multiple times.
+       --print-passes              Lists the passes and if they are enabled, then exits.
+       --emit (tokens | ir | bytecode)
+                                   Prints the optimized code instead of running it. `tokens`
+                                   prints the token tree with the source spans, `ir` the
+                                   instructions with the jump targets and `bytecode` the
+                                   instructions like the interpreter executes them.
+       --dump-after <pass>         Prints the code to stderr after the pass was executed. The
+                                   format is set with --emit. (Default: tokens)
 
 Settings:
     -c --cell (u8 | u16 | u32)     This sets the type for the buffer cell. (Default: u8)
-    -b --buffer <usize>            This defines the size of the buffer. (Default: 30000)
+    -b --buffer <size>             This defines the size of the buffer in cells. The size can
+                                   end with K, M or G. (Default: 30000)
+    -t --tape (vec | paged | infinite)
+                                   This sets the buffer implementation. `vec` allocates all cells
+                                   at the start. `paged` allocates pages on the first write, this
+                                   allows huge buffers like `-b 4G`. `infinite` grows the buffer
+                                   on both ends instead of wrapping around, `-b` is the initial
+                                   size. (Default: vec)
+       --banks <n>                 This sets the number of memory banks. Every bank has its own
+                                   buffer and data pointer, `|` switches between them. (Default: 1)
+
+IO:
+       --input <file>              Reads the input for `,` from the given file. (Default: console)
+       --input-str <text>          Reads the input for `,` from the given text.
+       --output <file>             Writes the output of `.` into the given file. (Default: console)
+       --data-file <file>          Adds a file to the file table of `:` and `;`. The first file
+                                   has the handle 0, it can be selected with `#`. This can be
+                                   used multiple times. (Default: bd_data.txt)
+       --sandbox <dir>             Restricts all file paths to the given directory. Relative
+                                   paths are resolved inside it. File operations that leave
+                                   the directory fail.
+       --no-file-io                Disables the file IO, all file operations fail. This wins
+                                   over --sandbox.
+
+Limits:
+       --max-steps <u64>           Aborts the execution after the given number of instructions.
+       --timeout <seconds>         Aborts the execution after the given time.
+       --max-memory <size>         Aborts if the memory buffer allocates more than the given size
+                                   in bytes. The size can end with K, M or G.
+       --max-depth <n>             Aborts if more than n procedure calls are nested.
+                                   (Default: 10000)
+
+Snapshots:
+       --snapshot <file>           Saves the interpreter state to the file if the execution is
+                                   aborted by the step limit, the timeout or the call depth limit,
+                                   or paused by SIGINT or SIGTERM. A second signal ends the
+                                   process without saving.
+       --resume <file>             Continues the execution from a saved state. The program and
+                                   the file table have to be the same.
+
+Exit codes:
+    0   The program finished.
+    2   The step limit was reached.
+    3   The timeout was reached.
+    4   The memory limit was exceeded.
+    5   The execution was paused by SIGINT or SIGTERM with --snapshot.
+    6   The procedure call depth limit was reached.
+    7   An undefined procedure was called.
+    8   A loop or procedure jumps outside of the program.
 ");
 }
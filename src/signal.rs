@@ -0,0 +1,21 @@
+//! Pauses the interpreter on SIGINT and SIGTERM, so the state can be saved
+//! with `--snapshot` when the process is preempted.
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::io::Error;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Sets the handle to `true` on SIGINT and SIGTERM. A second signal before
+/// the interpreter paused ends the process with the usual exit code of a
+/// killed process, like a blocking read would otherwise prevent it.
+pub fn pause_on_signal(handle: Arc<AtomicBool>) -> Result<(), Error> {
+    for signal in [SIGINT, SIGTERM].iter().copied() {
+        // The shutdown checks the handle before it's set by the same signal
+        flag::register_conditional_shutdown(signal, 128 + signal, handle.clone())?;
+        flag::register(signal, handle.clone())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use crate::Cell;
+use crate::interpreter::RunStats;
+use crate::operations::io::{FileIoState, FileHandleState};
+use std::io::{Read, Write, Error, ErrorKind};
+use std::fs::File;
+use std::mem;
+
+const MAGIC: &[u8; 4] = b"BDS4";
+
+/// The complete state of an interpreter. It can be written to a `.bds` file
+/// and restored later to continue the execution.
+///
+/// The console IO is not part of the snapshot. A resumed program continues
+/// reading from the console IO that is given to the new interpreter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot<T: Cell> {
+    pub banks: Vec<BankState<T>>,
+    /// The active bank.
+    pub bank: usize,
+    /// The defined procedures as `(procedure, start)`.
+    pub procedures: Vec<(usize, usize)>,
+    /// The positions of the active procedure calls.
+    pub call_stack: Vec<usize>,
+    pub inst_ptr: usize,
+    pub stats: RunStats,
+    /// A hash of the instructions to detect snapshots from different programs.
+    pub program_hash: u64,
+    pub io_state: FileIoState<T>,
+}
+
+/// The memory buffer and the data pointer of a bank.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BankState<T: Cell> {
+    pub size: usize,
+    /// The index of the cell where the program started.
+    pub origin: usize,
+    /// The non zero parts of the buffer as `(start, cells)`.
+    pub segments: Vec<(usize, Vec<T>)>,
+    pub index: usize,
+}
+
+impl<T> Snapshot<T>
+    where T: Cell
+{
+    pub fn save(&self, file_name: &str) -> Result<(), Error> {
+        let mut file = File::create(file_name)?;
+        self.write_to(&mut file)
+    }
+
+    pub fn load(file_name: &str) -> Result<Self, Error> {
+        let mut file = File::open(file_name)?;
+        Self::read_from(&mut file)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[mem::size_of::<T>() as u8])?;
+        write_u64(writer, self.program_hash)?;
+        write_u64(writer, self.inst_ptr as u64)?;
+
+        write_u64(writer, self.stats.steps)?;
+        write_u64(writer, self.stats.max_index as u64)?;
+        write_u64(writer, self.stats.final_index as u64)?;
+        write_u64(writer, self.stats.console_reads)?;
+        write_u64(writer, self.stats.console_writes)?;
+        write_u64(writer, self.stats.file_reads)?;
+        write_u64(writer, self.stats.file_writes)?;
+
+        write_u64(writer, self.bank as u64)?;
+        write_u64(writer, self.banks.len() as u64)?;
+        for bank in &self.banks {
+            write_u64(writer, bank.size as u64)?;
+            write_u64(writer, bank.origin as u64)?;
+            write_u64(writer, bank.index as u64)?;
+            write_u64(writer, bank.segments.len() as u64)?;
+            for (start, cells) in &bank.segments {
+                write_u64(writer, *start as u64)?;
+                write_cells(writer, cells)?;
+            }
+        }
+
+        write_u64(writer, self.procedures.len() as u64)?;
+        for (procedure, start) in &self.procedures {
+            write_u64(writer, *procedure as u64)?;
+            write_u64(writer, *start as u64)?;
+        }
+        write_u64(writer, self.call_stack.len() as u64)?;
+        for call in &self.call_stack {
+            write_u64(writer, *call as u64)?;
+        }
+
+        write_u64(writer, self.io_state.selected as u64)?;
+        write_u64(writer, self.io_state.handles.len() as u64)?;
+        for handle in &self.io_state.handles {
+            write_cells(writer, &handle.r_buffer)?;
+            write_u64(writer, handle.r_index as u64)?;
+            write_cells(writer, &handle.w_buffer)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "this is not a snapshot file"));
+        }
+
+        let mut cell_size = [0u8; 1];
+        reader.read_exact(&mut cell_size)?;
+        if cell_size[0] as usize != mem::size_of::<T>() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("the snapshot uses {} byte cells", cell_size[0])));
+        }
+
+        let program_hash = read_u64(reader)?;
+        let inst_ptr = read_usize(reader)?;
+
+        let stats = RunStats {
+            steps: read_u64(reader)?,
+            max_index: read_usize(reader)?,
+            final_index: read_usize(reader)?,
+            console_reads: read_u64(reader)?,
+            console_writes: read_u64(reader)?,
+            file_reads: read_u64(reader)?,
+            file_writes: read_u64(reader)?,
+        };
+
+        let bank = read_usize(reader)?;
+        let bank_count = read_usize(reader)?;
+        let mut banks = Vec::new();
+        for _ in 0..bank_count {
+            let size = read_usize(reader)?;
+            let origin = read_usize(reader)?;
+            let index = read_usize(reader)?;
+            let segment_count = read_usize(reader)?;
+            let mut segments = Vec::new();
+            for _ in 0..segment_count {
+                let start = read_usize(reader)?;
+                segments.push((start, read_cells(reader)?));
+            }
+
+            banks.push(BankState {
+                size,
+                origin,
+                segments,
+                index,
+            });
+        }
+
+        let procedure_count = read_usize(reader)?;
+        let mut procedures = Vec::new();
+        for _ in 0..procedure_count {
+            procedures.push((read_usize(reader)?, read_usize(reader)?));
+        }
+        let call_count = read_usize(reader)?;
+        let mut call_stack = Vec::new();
+        for _ in 0..call_count {
+            call_stack.push(read_usize(reader)?);
+        }
+
+        let selected = read_usize(reader)?;
+        let handle_count = read_usize(reader)?;
+        let mut handles = Vec::new();
+        for _ in 0..handle_count {
+            handles.push(FileHandleState {
+                r_buffer: read_cells(reader)?,
+                r_index: read_usize(reader)?,
+                w_buffer: read_cells(reader)?,
+            });
+        }
+
+        Ok(Snapshot {
+            banks,
+            bank,
+            procedures,
+            call_stack,
+            inst_ptr,
+            stats,
+            program_hash,
+            io_state: FileIoState {
+                selected,
+                handles,
+            },
+        })
+    }
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> Result<(), Error> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_usize<R: Read>(reader: &mut R) -> Result<usize, Error> {
+    let value = read_u64(reader)?;
+    if value > usize::MAX as u64 {
+        return Err(Error::new(ErrorKind::InvalidData, "the value is too large for this platform"));
+    }
+
+    Ok(value as usize)
+}
+
+/// Cells are stored with their length followed by the little endian bytes
+/// of each cell.
+fn write_cells<W: Write, T: Cell>(writer: &mut W, cells: &[T]) -> Result<(), Error> {
+    write_u64(writer, cells.len() as u64)?;
+
+    let mut bytes = Vec::with_capacity(mem::size_of_val(cells));
+    for cell in cells {
+        let value = cell.to_usize().to_le_bytes();
+        bytes.extend_from_slice(&value[..mem::size_of::<T>()]);
+    }
+
+    writer.write_all(&bytes)
+}
+
+fn read_cells<R: Read, T: Cell>(reader: &mut R) -> Result<Vec<T>, Error> {
+    let len = read_usize(reader)?;
+    let cell_size = mem::size_of::<T>();
+
+    // The length is not trusted to allocate the buffer
+    let size = len.saturating_mul(cell_size);
+    let mut bytes = Vec::new();
+    reader.take(size as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != size {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "the snapshot ended inside a buffer"));
+    }
+
+    Ok(bytes.chunks(cell_size)
+        .map(|chunk| {
+            let mut value = [0u8; mem::size_of::<usize>()];
+            value[..cell_size].copy_from_slice(chunk);
+            T::from_usize(usize::from_le_bytes(value))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_round_trip<T: Cell>(buffer: Vec<T>) {
+        let snapshot = Snapshot {
+            banks: vec![
+                BankState {
+                    size: 1 << 40,
+                    origin: 5,
+                    segments: vec![(0, buffer.clone()), (1 << 32, buffer.clone())],
+                    index: 3,
+                },
+                BankState {
+                    size: 16,
+                    origin: 0,
+                    segments: Vec::new(),
+                    index: 7,
+                },
+            ],
+            bank: 1,
+            procedures: vec![(0, 4), (255, 9)],
+            call_stack: vec![12, 12, 20],
+            inst_ptr: 42,
+            stats: RunStats {
+                steps: 100,
+                max_index: 7,
+                final_index: 3,
+                console_reads: 1,
+                console_writes: 2,
+                file_reads: 3,
+                file_writes: 4,
+            },
+            program_hash: 0xdead_beef,
+            io_state: FileIoState {
+                selected: 1,
+                handles: vec![
+                    FileHandleState { r_buffer: Vec::new(), r_index: 0, w_buffer: buffer.clone() },
+                    FileHandleState { r_buffer: buffer, r_index: 2, w_buffer: Vec::new() },
+                ],
+            },
+        };
+
+        let mut data = Vec::new();
+        snapshot.write_to(&mut data).unwrap();
+
+        let loaded: Snapshot<T> = Snapshot::read_from(&mut data.as_slice()).unwrap();
+        assert_eq!(loaded, snapshot);
+
+        // Every truncated snapshot is rejected
+        for len in 0..data.len() {
+            assert!(Snapshot::<T>::read_from(&mut &data[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        test_round_trip(vec![0u8, 1, 2, 255]);
+        test_round_trip(vec![0u16, 1, 2, 0xffff]);
+        test_round_trip(vec![0u32, 1, 2, 0xffff_ffff]);
+    }
+
+    #[test]
+    fn test_snapshot_cell_size() {
+        let snapshot: Snapshot<u8> = Snapshot {
+            banks: vec![BankState {
+                size: 3,
+                origin: 0,
+                segments: vec![(0, vec![1, 2, 3])],
+                index: 0,
+            }],
+            bank: 0,
+            procedures: Vec::new(),
+            call_stack: Vec::new(),
+            inst_ptr: 0,
+            stats: RunStats::default(),
+            program_hash: 0,
+            io_state: FileIoState {
+                selected: 0,
+                handles: Vec::new(),
+            },
+        };
+
+        let mut data = Vec::new();
+        snapshot.write_to(&mut data).unwrap();
+
+        assert!(Snapshot::<u16>::read_from(&mut data.as_slice()).is_err());
+        assert!(Snapshot::<u8>::read_from(&mut b"BDS3".as_ref()).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+pub mod fmt;
+pub mod lint;
+pub mod minify;
+
+use crate::parser::{self, MacroProcessor, Preprocessor, Source, MAX_NESTING};
+use std::fs;
+use std::path::PathBuf;
+
+/// Loads the file, resolves the includes and expands the macros if they are
+/// enabled. The error is the exit code, the message was already printed.
+fn load_source(file: &str, lib_paths: &[PathBuf], macros: bool) -> Result<Source, i32> {
+    let code = match fs::read_to_string(file) {
+        Ok(code) => code,
+        Err(error) => {
+            println!("Unable to load the file \"{}\": {}", file, error);
+            return Err(-1);
+        }
+    };
+
+    let source = match Preprocessor::new(lib_paths.to_vec()).process(&code, Some(file)) {
+        Ok(source) => source,
+        Err(error) => {
+            println!("Unable to resolve the includes: {}", error);
+            return Err(-1);
+        }
+    };
+    let source = if macros {
+        MacroProcessor::new().expand(source).map_err(|error| {
+            println!("Unable to expand the macros: {}", error);
+            -1
+        })?
+    } else {
+        source
+    };
+
+    if let Some(start) = parser::find_deep_block(&source.code, MAX_NESTING) {
+        println!("{}: The block is nested deeper than {} levels", source.location(start), MAX_NESTING);
+        return Err(-1);
+    }
+
+    Ok(source)
+}
@@ -0,0 +1,376 @@
+use crate::parser::{self, Lexer, Token};
+use std::fs;
+
+const INDENT: &str = "    ";
+/// Blocks without nested blocks are written in one line up to this length,
+/// including the brackets.
+const INLINE_BLOCK_LEN: usize = 32;
+/// Directive lines are replaced by this marker while the code is formatted.
+const DIRECTIVE_MARKER: char = '\u{E000}';
+
+/// Formats Braindamage code:
+///
+/// * Lines are indented by the nesting depth of loops and procedures.
+/// * Short blocks without nested blocks stay in one line, like `[->+<]`.
+/// * Runs of the same operator are not split when a line is wrapped.
+/// * Comments and the line breaks in comments are kept, the whitespace around
+///   them is normalized. Include and macro definition lines are not changed.
+#[derive(Debug, Default)]
+pub struct Formatter {
+    /// Lines are wrapped between runs if they get longer.
+    width: Option<usize>,
+}
+
+impl Formatter {
+    pub fn new(width: Option<usize>) -> Self {
+        Formatter {
+            width,
+        }
+    }
+
+    pub fn format(&self, code: &str) -> String {
+        // Directives are not lexed, they can contain operators like `.`
+        let mut directives = Vec::new();
+        let code: String = code.split_inclusive('\n')
+            .map(|line| {
+                if parser::is_directive_line(line) {
+                    directives.push(line.trim().to_string());
+                    let newline = if line.ends_with('\n') { "\n" } else { "" };
+                    format!("{}{}{}", DIRECTIVE_MARKER, directives.len() - 1, newline)
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect();
+
+        let tokens: Vec<Token<u8>> = Lexer::new().lex_string(&code);
+        let mut output = Output::new(self.width);
+        self.write_tokens(&mut output, &tokens, &code);
+
+        let mut lines = output.finish();
+        for line in lines.iter_mut() {
+            if let Some(index) = line.trim_start().strip_prefix(DIRECTIVE_MARKER) {
+                if let Some(directive) = index.parse::<usize>().ok().and_then(|index| directives.get(index)) {
+                    *line = line.replace(&format!("{}{}", DIRECTIVE_MARKER, index), directive);
+                }
+            }
+        }
+
+        let mut result = lines.join("\n");
+        if !result.is_empty() {
+            result.push('\n');
+        }
+        result
+    }
+
+    fn write_tokens(&self, output: &mut Output, tokens: &[Token<u8>], code: &str) {
+        let mut index = 0;
+        while index < tokens.len() {
+            match &tokens[index] {
+                Token::Instruction(_, span) => {
+                    // Collect the run of the same operator
+                    let op = &code[span.start..span.end];
+                    let mut end = index + 1;
+                    while let Some(Token::Instruction(_, next)) = tokens.get(end) {
+                        if &code[next.start..next.end] != op {
+                            break;
+                        }
+                        end += 1;
+                    }
+
+                    output.push_code(&op.repeat(end - index));
+                    index = end;
+                    continue;
+                },
+                Token::LoopBlock(content, span) => self.write_block(output, content, &code[span.start..span.end], ']', code),
+                Token::ProcedureBlock(content, span) => self.write_block(output, content, &code[span.start..span.end], ')', code),
+                Token::Comment(text, _) => output.push_comment(text),
+            }
+
+            index += 1;
+        }
+    }
+
+    /// Writes a block, `text` is the source of the block including the brackets.
+    fn write_block(&self, output: &mut Output, content: &[Token<u8>], text: &str, close: char, code: &str) {
+        let open = &text[..1];
+        // The block might not be closed in the source
+        let close = if text.len() > 1 && text.ends_with(close) { &text[text.len() - 1..] } else { "" };
+
+        let multiline = content.iter().any(|token| match token {
+            Token::LoopBlock(..) | Token::ProcedureBlock(..) => true,
+            Token::Comment(text, _) => text.contains('\n'),
+            Token::Instruction(..) => false,
+        });
+        if !multiline && !close.is_empty() {
+            let mut inline = Output::new(None);
+            self.write_tokens(&mut inline, content, code);
+            let len = inline.current.len() + 2;
+            let fits = self.width.is_none_or(|width| INDENT.len() * output.depth + len <= width);
+            if len <= INLINE_BLOCK_LEN && fits {
+                output.push_code(&format!("{}{}{}", open, inline.current, close));
+                return;
+            }
+        }
+
+        output.push_code(open);
+        output.end_line();
+        output.depth += 1;
+        self.write_tokens(output, content, code);
+        output.end_line();
+        output.depth -= 1;
+        if !close.is_empty() {
+            output.push_code(close);
+            output.break_before_code = true;
+        }
+    }
+}
+
+/// The formatted lines.
+struct Output {
+    width: Option<usize>,
+    /// The finished lines with their depth.
+    lines: Vec<(usize, String)>,
+    current: String,
+    /// The depth of the current line.
+    line_depth: usize,
+    depth: usize,
+    /// A space is added before the next text in the current line.
+    space_pending: bool,
+    /// The next code starts a new line, comments can still follow in the current line.
+    break_before_code: bool,
+}
+
+impl Output {
+    fn new(width: Option<usize>) -> Self {
+        Output {
+            width,
+            lines: Vec::new(),
+            current: String::new(),
+            line_depth: 0,
+            depth: 0,
+            space_pending: false,
+            break_before_code: false,
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.current.is_empty() {
+            self.line_depth = self.depth;
+        } else if self.space_pending {
+            self.current.push(' ');
+        }
+
+        self.space_pending = false;
+        self.current.push_str(text);
+    }
+
+    fn push_code(&mut self, code: &str) {
+        if self.break_before_code {
+            self.end_line();
+        }
+
+        if let Some(width) = self.width {
+            let used = INDENT.len() * self.line_depth + self.current.len();
+            if !self.current.is_empty() && used + code.len() > width {
+                self.end_line();
+            }
+
+            // A run that is longer than the line is split
+            let available = width.saturating_sub(INDENT.len() * self.depth).max(1);
+            let mut code = code;
+            while code.len() > available && code.is_char_boundary(available) {
+                self.push_text(&code[..available]);
+                self.end_line();
+                code = &code[available..];
+            }
+            self.push_text(code);
+            return;
+        }
+
+        self.push_text(code);
+    }
+
+    fn push_comment(&mut self, text: &str) {
+        let mut segments = text.split('\n').peekable();
+        let mut first = true;
+        while let Some(segment) = segments.next() {
+            let last = segments.peek().is_none();
+            if !first {
+                self.end_line();
+                if segment.trim().is_empty() && !last {
+                    self.blank_line();
+                }
+            }
+
+            let trimmed = segment.trim();
+            if !trimmed.is_empty() {
+                if segment.starts_with(char::is_whitespace) {
+                    self.space_pending = true;
+                }
+                self.push_text(trimmed);
+            }
+            if segment.ends_with(char::is_whitespace) && !segment.is_empty() {
+                self.space_pending = true;
+            }
+
+            first = false;
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.current.is_empty() {
+            let line = std::mem::take(&mut self.current);
+            match self.lines.last_mut() {
+                // Comment text at the start of a line would become an include or
+                // a macro definition, it stays in the previous line
+                Some((_, previous)) if parser::is_directive_line(&line)
+                    && !previous.is_empty() && !previous.starts_with(DIRECTIVE_MARKER) =>
+                {
+                    previous.push(' ');
+                    previous.push_str(&line);
+                },
+                _ => self.lines.push((self.line_depth, line)),
+            }
+        }
+
+        self.space_pending = false;
+        self.break_before_code = false;
+    }
+
+    fn blank_line(&mut self) {
+        if self.lines.last().is_some_and(|(_, line)| !line.is_empty()) {
+            self.lines.push((0, String::new()));
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.end_line();
+        while self.lines.last().is_some_and(|(_, line)| line.is_empty()) {
+            self.lines.pop();
+        }
+
+        self.lines.into_iter()
+            .map(|(depth, line)| if line.is_empty() { line } else { format!("{}{}", INDENT.repeat(depth), line) })
+            .collect()
+    }
+}
+
+/// Formats the file in place. In check mode the file is not changed and `1`
+/// is returned if the file is not formatted. Returns the exit code.
+pub fn run(file: &str, check: bool, width: Option<usize>) -> i32 {
+    let code = match fs::read_to_string(file) {
+        Ok(code) => code,
+        Err(error) => {
+            println!("Unable to load the file \"{}\": {}", file, error);
+            return -1;
+        }
+    };
+    if let Some(start) = parser::find_deep_block(&code, parser::MAX_NESTING) {
+        let line = code[..start].matches('\n').count() + 1;
+        println!("{}:{}: The block is nested deeper than {} levels", file, line, parser::MAX_NESTING);
+        return -1;
+    }
+
+    let formatted = Formatter::new(width).format(&code);
+    if formatted == code {
+        return 0;
+    }
+
+    if check {
+        println!("The file \"{}\" is not formatted", file);
+        return 1;
+    }
+
+    if let Err(error) = fs::write(file, formatted) {
+        println!("Unable to write the file \"{}\": {}", file, error);
+        return -1;
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_format(code: &str, expected: &str) {
+        let formatter = Formatter::default();
+        assert_eq!(formatter.format(code), expected);
+        // Formatting is idempotent
+        assert_eq!(formatter.format(expected), expected);
+    }
+
+    #[test]
+    fn test_indent() {
+        assert_format(
+            "++++++++[>++++[>++>+++<<-]>+<<-]>>.",
+            "++++++++[\n    >++++[>++>+++<<-]>+<<-\n]\n>>.\n");
+        assert_format("  +[-]  >.  ", "+[-] >.\n");
+        assert_format("(+%)", "(+%)\n");
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_format(
+            "print A\n\n\n+++ set [\n  - loop ]   .",
+            "print A\n\n+++ set [\n    - loop\n]\n.\n");
+        assert_format("+[- x] done", "+[- x] done\n");
+        // Repeat counts of macros stay attached
+        assert_format("+*16 >*2", "+*16 >*2\n");
+    }
+
+    #[test]
+    fn test_directives() {
+        assert_format(
+            "  !include \"lib/print.bd\"\n$inc = +*2;\n[$inc.]",
+            "!include \"lib/print.bd\"\n$inc = +*2;\n[$inc.]\n");
+    }
+
+    /// Asserts that formatting only changes whitespace. The directive lines
+    /// and the lexed instructions stay the same.
+    fn assert_same_program(code: &str) {
+        fn split(code: &str) -> (Vec<&str>, String) {
+            let (directives, lines): (Vec<&str>, Vec<&str>) = code.split_inclusive('\n')
+                .partition(|line| parser::is_directive_line(line));
+            (directives.into_iter().map(str::trim).collect(), lines.concat())
+        }
+
+        let formatted = Formatter::default().format(code);
+        let (directives, rest) = split(code);
+        let (formatted_directives, formatted_rest) = split(&formatted);
+        assert_eq!(formatted_directives, directives, "{:?}", formatted);
+
+        let without_whitespace = |text: &str| text.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+        assert_eq!(without_whitespace(&formatted_rest), without_whitespace(&rest), "{:?}", formatted);
+        let lexer: Lexer<u8> = Lexer::new();
+        let instructions = |text: &str| lexer.flatten(Lexer::new().lex_string(text));
+        assert_eq!(instructions(&formatted_rest), instructions(&rest), "{:?}", formatted);
+    }
+
+    #[test]
+    fn test_directive_like_comments() {
+        // A comment after a bracket is not moved to the start of a line
+        assert_format("+[!include \"x.bd\"\n[-]]", "+[ !include \"x.bd\"\n    [-]\n]\n");
+        assert_format("+[$x = +;\n[-]]", "+[ $x = +;\n    [-]\n]\n");
+
+        assert_same_program("+[!include \"x.bd\"\n[-]]");
+        assert_same_program("+[$x = +;\n[-]]");
+        assert_same_program("!include \"a.bd\"\n$inc = +*2;\n[$inc.[\n$y =\n]]\n(  $z = -;)");
+        assert_same_program("print A\n\n\n+++ set [\n  - loop ]   .");
+    }
+
+    #[test]
+    fn test_width() {
+        let formatter = Formatter::new(Some(8));
+        let formatted = formatter.format("++++++>>>>----[<<<<<<<<<<<<.]");
+        assert_eq!(formatted, "++++++\n>>>>----\n[\n    <<<<\n    <<<<\n    <<<<\n    .\n]\n");
+        assert_eq!(formatter.format(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_unclosed_block() {
+        assert_format("+[-", "+[\n    -\n");
+        assert_format("+]", "+]\n");
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{Cell, Instruction};
+use crate::parser::{Lexer, Span, Token};
+use crate::parser::analysis::State;
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+
+/// The smallest cell has 256 values, a change that is not a multiple of 256
+/// can't wrap to zero with any cell type.
+const MIN_CELL_VALUES: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lint {
+    /// The loop doesn't change the cell and the cell is not zero.
+    InfiniteLoop,
+    /// The loop doesn't change the cell, it never ends if it's entered.
+    MaybeInfiniteLoop,
+    /// The loop changes values and moves the pointer by this offset.
+    UnbalancedLoop(isize),
+    /// The cell is always zero when the loop is reached.
+    UnreachableLoop,
+    /// No `:` or `&` can write the `0` that saves the file.
+    UnterminatedWrite,
+    /// The `0` at the end of the file is not checked by a loop.
+    UncheckedRead,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::InfiniteLoop => write!(f, "The loop never terminates, the cell is not 0 and not changed in the loop"),
+            Lint::MaybeInfiniteLoop => write!(f, "The loop never terminates if it's entered, the cell is not changed in the loop"),
+            Lint::UnbalancedLoop(offset) => write!(f, "The loop moves the pointer by {} in every iteration", offset),
+            Lint::UnreachableLoop => write!(f, "The loop is never executed, the cell is always 0"),
+            Lint::UnterminatedWrite => write!(f, "The file is never saved, no `:` or `&` writes a 0"),
+            Lint::UncheckedRead => write!(f, "The 0 at the end of the file is not checked by a loop"),
+        }
+    }
+}
+
+/// A lint and the span of the code it refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub span: Span,
+}
+
+/// Returns `true` if the current cell is not zero with every cell type.
+fn is_nonzero(state: &State<u32>) -> bool {
+    state.current().is_some_and(|value| value.to_usize() % MIN_CELL_VALUES != 0)
+}
+
+/// Finds common mistakes in Braindamage code. The known cell values come
+/// from the abstract interpretation of the code.
+#[derive(Debug, Default)]
+pub struct Linter {
+    warnings: Vec<Warning>,
+    /// The `:` and `&` instructions and if they might write a zero.
+    writes: Vec<(Span, bool)>,
+    /// The size of the tape if it wraps around.
+    wrap: Option<usize>,
+}
+
+impl Linter {
+    pub fn new(wrap: Option<usize>) -> Self {
+        Linter {
+            wrap,
+            ..Linter::default()
+        }
+    }
+
+    pub fn lint(mut self, code: &str) -> Vec<Warning> {
+        let tokens: Vec<Token<u32>> = Lexer::new().lex_string(code);
+        self.check_block(&tokens, State::start(self.wrap), false);
+
+        if !self.writes.iter().any(|(_, zero)| *zero) {
+            if let Some((span, _)) = self.writes.last() {
+                self.warn(Lint::UnterminatedWrite, *span);
+            }
+        }
+
+        self.warnings.sort_by_key(|warning| warning.span.start);
+        self.warnings
+    }
+
+    fn warn(&mut self, lint: Lint, span: Span) {
+        self.warnings.push(Warning {
+            lint,
+            span,
+        });
+    }
+
+    /// Checks the tokens of a block, `state` is the state at the start of
+    /// the block.
+    fn check_block(&mut self, tokens: &[Token<u32>], mut state: State<u32>, is_loop: bool) {
+        for (index, token) in tokens.iter().enumerate() {
+            match token {
+                Token::Instruction(Instruction::FileIoWrite | Instruction::FileIoAppend, span) => {
+                    self.writes.push((*span, !is_nonzero(&state)));
+                },
+                Token::Instruction(Instruction::FileIoRead, span) if !is_read_checked(&tokens[index + 1..], is_loop) => {
+                    self.warn(Lint::UncheckedRead, *span);
+                },
+                Token::LoopBlock(content, span) => {
+                    if state.is_zero() {
+                        self.warn(Lint::UnreachableLoop, *span);
+                        continue;
+                    }
+
+                    if content.iter().all(is_inert) {
+                        let lint = if is_nonzero(&state) { Lint::InfiniteLoop } else { Lint::MaybeInfiniteLoop };
+                        self.warn(lint, *span);
+                    } else if let Some(offset) = loop_offset(content).filter(|offset| *offset != 0) {
+                        // Scan loops like `[>]` only move the pointer
+                        if !content.iter().all(is_move) {
+                            self.warn(Lint::UnbalancedLoop(offset), *span);
+                        }
+                    }
+
+                    self.check_block(content, state.enter_loop(content), true);
+                },
+                // The definition can be called from anywhere
+                Token::ProcedureBlock(content, _) => self.check_block(content, State::unknown(self.wrap), false),
+                _ => {},
+            }
+
+            state.apply(token);
+        }
+    }
+}
+
+/// Returns `true` if the token changes neither the cell nor the pointer.
+fn is_inert(token: &Token<u32>) -> bool {
+    matches!(token, Token::Comment(..) | Token::Instruction(Instruction::NoOp | Instruction::IoWrite, _))
+}
+
+fn is_move(token: &Token<u32>) -> bool {
+    matches!(token, Token::Comment(..)
+        | Token::Instruction(Instruction::IncreaseIndex(_) | Instruction::DecreaseIndex(_) | Instruction::NoOp, _))
+}
+
+/// Returns the pointer offset of one iteration of the loop body, `None` if
+/// it's unknown because of nested unbalanced loops, calls or bank switches.
+fn loop_offset(content: &[Token<u32>]) -> Option<isize> {
+    let mut offset = 0;
+    for token in content {
+        match token {
+            Token::Instruction(Instruction::IncreaseIndex(x), _) => offset += *x as isize,
+            Token::Instruction(Instruction::DecreaseIndex(x), _) => offset -= *x as isize,
+            Token::Instruction(Instruction::CallProcedure | Instruction::BankSwitch, _) => return None,
+            Token::LoopBlock(content, _) if loop_offset(content)? != 0 => return None,
+            _ => {},
+        }
+    }
+
+    Some(offset)
+}
+
+/// Returns `true` if the value of a `;` is checked by a loop before it's
+/// changed. The end of a loop body checks the value with the loop condition.
+fn is_read_checked(rest: &[Token<u32>], is_loop: bool) -> bool {
+    for token in rest {
+        match token {
+            Token::LoopBlock(..) => return true,
+            Token::Comment(..) => {},
+            Token::Instruction(Instruction::NoOp | Instruction::IoWrite
+                | Instruction::FileIoWrite | Instruction::FileIoAppend, _) => {},
+            _ => return false,
+        }
+    }
+
+    is_loop
+}
+
+/// Lints the file for a tape that wraps around after `wrap` cells and prints
+/// the warnings. Returns `1` if there are warnings.
+pub fn run(file: &str, lib_paths: &[PathBuf], macros: bool, wrap: Option<usize>) -> i32 {
+    let source = match super::load_source(file, lib_paths, macros) {
+        Ok(source) => source,
+        Err(exit_code) => return exit_code,
+    };
+
+    let warnings = Linter::new(wrap).lint(&source.code);
+    for warning in &warnings {
+        println!("{}: warning: {}", source.location(warning.span.start), warning.lint);
+    }
+
+    if warnings.is_empty() { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lints(code: &str) -> Vec<Lint> {
+        Linter::new(None).lint(code).into_iter().map(|warning| warning.lint).collect()
+    }
+
+    #[test]
+    fn test_loops() {
+        assert_eq!(lints("+[]"), vec![Lint::InfiniteLoop]);
+        assert_eq!(lints(",[.]"), vec![Lint::MaybeInfiniteLoop]);
+        assert_eq!(lints("[-]+[-][>+<-]"), vec![Lint::UnreachableLoop, Lint::UnreachableLoop]);
+        assert_eq!(lints(",[->+>]"), vec![Lint::UnbalancedLoop(2)]);
+        // Scan loops and loops with unknown offsets are fine
+        assert_eq!(lints(",[>],[->+<[>]<]"), vec![]);
+
+        // The pointer reaches the first cell again on a tape with 4 cells
+        let warnings = Linter::new(Some(4)).lint("+>>>>[.[-]]");
+        assert_eq!(warnings, vec![]);
+    }
+
+    #[test]
+    fn test_file_io() {
+        assert_eq!(lints(";[.;]"), vec![]);
+        assert_eq!(lints(";.;+"), vec![Lint::UncheckedRead, Lint::UncheckedRead]);
+        assert_eq!(lints("+:+:"), vec![Lint::UnterminatedWrite]);
+        assert_eq!(lints("+:-:"), vec![]);
+        // Overflowing cells might write a zero
+        assert_eq!(lints(",:"), vec![]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let warnings = Linter::new(None).lint("+.\n  +[]");
+        assert_eq!(warnings, vec![Warning { lint: Lint::InfiniteLoop, span: Span::new(6, 8) }]);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::buffer::TapeKind;
+use crate::parser::{printer, Parser, ParserMode, Pass};
+use std::path::PathBuf;
+
+/// Returns the smallest equivalent source of the code. Comments are removed,
+/// opposite instructions like `+-` cancel each other and loops that can never
+/// be executed are dropped.
+pub fn minify(code: &str) -> String {
+    // The cell type doesn't matter, all counts fit into a u32. The other
+    // passes are not used, they can make the source longer.
+    let mode = ParserMode::DEBUG
+        .with_pass(Pass::StripComments, true)
+        .with_pass(Pass::CancelOpposite, true)
+        .with_pass(Pass::DeadLoops, true);
+    // The tape of the program is unknown. On a tape with a single cell every
+    // offset could be the same cell, what is known there holds on every tape.
+    let mut parser: Parser<u32> = Parser::new(mode, TapeKind::Vec, 1);
+
+    printer::print_tokens(&parser.parse_tokens(code))
+}
+
+/// Resolves the includes and macros of the file and prints the minified
+/// source. Returns the exit code.
+pub fn run(file: &str, lib_paths: &[PathBuf], macros: bool) -> i32 {
+    match super::load_source(file, lib_paths, macros) {
+        Ok(source) => {
+            println!("{}", minify(&source.code));
+            0
+        },
+        Err(exit_code) => exit_code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify() {
+        assert_eq!(minify("[comment, with. ops] ,+++ set\n>+-< [-]\n[->+<]"), ",+++[-]");
+        assert_eq!(minify("+++[-]>+[-]"), "+++[-]>+[-]");
+        assert_eq!(minify(",[-][>][<]>.(+-)_"), ",[-]>.()_");
+        assert_eq!(minify("hello"), "");
+        // The value of other cells is unknown, the pointer might wrap around the tape
+        assert_eq!(minify("+>>>>[.[-]]"), "+>>>>[.[-]]");
+        assert_eq!(minify(",[>[-]<[x]]>[-][+]"), ",[>[-]<[]]>[-]");
+        assert_eq!(minify(",[-].[x]>[y]"), ",[-].>[]");
+    }
+}